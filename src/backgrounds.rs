@@ -0,0 +1,331 @@
+use std::sync::Arc;
+use image::{imageops::{self, FilterType}, RgbImage};
+
+use crate::images::{self, ScaleMode};
+use crate::utils::{self, Result};
+
+/// The blurred backdrop is computed at this fraction of the window size and enlarged.
+const BLUR_REDUCTION: u32 = 16;
+const BLUR_SIGMA: f32 = 2.0;
+
+/*
+A background is drawn behind the image instead of the flat background color:
+
+linear-gradient([ANGLE | to SIDE [SIDE],] COLOR [POSITION%], COLOR [POSITION%], ...)
+radial-gradient([circle | ellipse,] COLOR [POSITION%], COLOR [POSITION%], ...)
+url(FILE) - the image tiled from the top left corner
+blur - a blurred copy of the image covering the window, the background color without an image
+
+ANGLE is in deg, rad or turn, 0deg pointing up and 90deg to the right, the gradient goes to the bottom
+by default. Radial gradients are centered and reach the farthest corners.
+*/
+
+/// The direction of a linear gradient.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// An angle in radians, clockwise from the top.
+    Angle(f64),
+    /// Towards a corner: the horizontal and the vertical sign, the gradient line is perpendicular
+    /// to the diagonal joining the other two corners.
+    Corner(f64, f64)
+}
+
+/// A color at a position along the gradient line, from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stop {
+    color: u32,
+    position: f64
+}
+
+/// What is drawn behind the image instead of the flat background color.
+#[derive(Debug, Clone)]
+pub enum Background {
+    Linear { direction: Direction, stops: Vec<Stop> },
+    Radial { circle: bool, stops: Vec<Stop> },
+    Tile(Arc<RgbImage>),
+    Blur
+}
+
+impl Background {
+    /// Parses the CSS-like background, the tile image is loaded from the file.
+    pub fn parse(spec: &str) -> Result<Self> {
+        const ERR: &str = "background must be linear-gradient(...), radial-gradient(...), url(FILE) or blur";
+        let spec = spec.trim();
+        if spec.eq_ignore_ascii_case("blur") {
+            return Ok(Background::Blur)
+        }
+        let (function, args) = spec.strip_suffix(')').and_then(|spec| spec.split_once('('))
+                                   .ok_or(ERR)?;
+        match function.trim().to_ascii_lowercase().as_str() {
+            "url" => {
+                let path = args.trim().trim_matches(|c| c == '"' || c == '\'');
+                let (img, _) = images::load_image(path, false).map_err(|err| format!("{}: {}", path, err))?;
+                Ok(Background::Tile(Arc::new(img)))
+            }
+            "linear-gradient" => {
+                let mut args = split_args(args);
+                let direction = match parse_direction(args[0]) {
+                    Some(direction) => {
+                        args.remove(0);
+                        direction
+                    }
+                    None => Direction::Angle(std::f64::consts::PI)
+                };
+                Ok(Background::Linear { direction, stops: parse_stops(&args)? })
+            }
+            "radial-gradient" => {
+                let mut args = split_args(args);
+                let shape = args[0].to_ascii_lowercase();
+                let circle = shape == "circle";
+                if circle || shape == "ellipse" {
+                    args.remove(0);
+                }
+                Ok(Background::Radial { circle, stops: parse_stops(&args)? })
+            }
+            _ => Err(ERR.into())
+        }
+    }
+
+    /// Fills the buffer with the background of the image, or of the `color` display without an image.
+    pub fn draw(&self, img: Option<&RgbImage>, color: u32, buffer: &mut [u32], width: usize, height: usize) {
+        if width == 0 || height == 0 {
+            return
+        }
+        match self {
+            Background::Linear { direction, stops } => {
+                let (dx, dy) = match *direction {
+                    Direction::Angle(angle) => (angle.sin(), -angle.cos()),
+                    Direction::Corner(sx, sy) => {
+                        let norm = (width as f64).hypot(height as f64);
+                        (sx * height as f64 / norm, sy * width as f64 / norm)
+                    }
+                };
+                let length = (width as f64 * dx).abs() + (height as f64 * dy).abs();
+                fill_with(buffer, width, height, |x, y| 0.5 + (x * dx + y * dy) / length, stops);
+            }
+            Background::Radial { circle, stops } => {
+                let (rx, ry) = if *circle {
+                    let radius = (width as f64).hypot(height as f64) / 2.0;
+                    (radius, radius)
+                }
+                else {
+                    (width as f64 / 2.0 * 2f64.sqrt(), height as f64 / 2.0 * 2f64.sqrt())
+                };
+                fill_with(buffer, width, height, |x, y| (x / rx).hypot(y / ry), stops);
+            }
+            Background::Tile(tile) => {
+                let (tile_width, tile_height) = (tile.width() as usize, tile.height() as usize);
+                let pixels: Vec<u32> = tile.pixels().map(|p| images::from_u8_rgb(p[0], p[1], p[2])).collect();
+                for (y, row) in buffer.chunks_exact_mut(width).enumerate() {
+                    let tile_row = &pixels[y % tile_height * tile_width..][..tile_width];
+                    for (pixel, tile_pixel) in row.iter_mut().zip(tile_row.iter().cycle()) {
+                        *pixel = *tile_pixel;
+                    }
+                }
+            }
+            Background::Blur => match img {
+                Some(img) => {
+                    // blurring a reduced copy is much faster and enlarging it smooths it further
+                    let (small_width, small_height) = ((width as u32).div_ceil(BLUR_REDUCTION),
+                                                       (height as u32).div_ceil(BLUR_REDUCTION));
+                    let (fill_width, fill_height) = ScaleMode::Fill.scaled_size(img.dimensions(),
+                                                                                (small_width, small_height));
+                    let small = imageops::thumbnail(img, fill_width, fill_height);
+                    let blurred = imageops::blur(&small, BLUR_SIGMA);
+                    let (x, y) = ((fill_width - small_width) / 2, (fill_height - small_height) / 2);
+                    let cropped = imageops::crop_imm(&blurred, x, y, small_width, small_height).to_image();
+                    let enlarged = imageops::resize(&cropped, width as u32, height as u32, FilterType::Triangle);
+                    for (pixel, p) in buffer.iter_mut().zip(enlarged.pixels()) {
+                        *pixel = images::from_u8_rgb(p[0], p[1], p[2]);
+                    }
+                }
+                None => buffer.fill(color)
+            }
+        }
+    }
+}
+
+/// Splits the function arguments at the commas outside of parentheses.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut list = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (index, ch) in args.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                list.push(args[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    list.push(args[start..].trim());
+    list
+}
+
+fn parse_direction(arg: &str) -> Option<Direction> {
+    let arg = arg.to_ascii_lowercase();
+    if let Some(sides) = arg.strip_prefix("to ") {
+        let (mut sx, mut sy) = (0.0, 0.0);
+        for side in sides.split_whitespace() {
+            match side {
+                "left" => sx = -1.0,
+                "right" => sx = 1.0,
+                "top" => sy = -1.0,
+                "bottom" => sy = 1.0,
+                _ => return None
+            }
+        }
+        return match (sx, sy) {
+            (0.0, 0.0) => None,
+            (0.0, _) | (_, 0.0) => Some(Direction::Angle(f64::atan2(sx, -sy))),
+            _ => Some(Direction::Corner(sx, sy))
+        }
+    }
+    let (value, unit) = arg.find(|c: char| c.is_ascii_alphabetic()).map(|index| arg.split_at(index))?;
+    let value: f64 = value.trim().parse().ok().filter(|value: &f64| value.is_finite())?;
+    match unit {
+        "deg" => Some(Direction::Angle(value.to_radians())),
+        "rad" => Some(Direction::Angle(value)),
+        "turn" => Some(Direction::Angle(value * std::f64::consts::TAU)),
+        _ => None
+    }
+}
+
+/// Parses the color stops, the missing positions are spread evenly between the given ones.
+fn parse_stops(args: &[&str]) -> Result<Vec<Stop>> {
+    if args.len() < 2 {
+        return Err("a gradient needs at least two colors".into())
+    }
+    let mut stops = Vec::new();
+    for arg in args {
+        let (color, position) = match arg.rsplit_once(' ').filter(|(_, position)| position.ends_with('%')) {
+            Some((color, position)) => {
+                let position = position.trim_end_matches('%').parse::<f64>().ok().filter(|position| position.is_finite())
+                                       .ok_or_else(|| format!("invalid color stop position: {}", arg))?;
+                (color.trim(), Some(position / 100.0))
+            }
+            None => (*arg, None)
+        };
+        let color = utils::parse_color(color).ok_or_else(|| format!("couldn't recognize a color name: {}", color))?;
+        stops.push((color, position));
+    }
+    let last = stops.len() - 1;
+    stops[0].1.get_or_insert(0.0);
+    stops[last].1.get_or_insert(1.0);
+    let mut stops: Vec<Stop> = stops.iter().enumerate().map(|(index, &(color, position))| {
+        let position = position.unwrap_or_else(|| {
+            let (before, start) = stops[..index].iter().enumerate().rev()
+                                                .find_map(|(at, stop)| stop.1.map(|position| (at, position))).unwrap();
+            let (after, end) = stops[index..].iter().enumerate()
+                                             .find_map(|(at, stop)| stop.1.map(|position| (index + at, position))).unwrap();
+            start + (end - start) * (index - before) as f64 / (after - before) as f64
+        });
+        Stop { color, position }
+    }).collect();
+    // the positions don't go back
+    for index in 1..stops.len() {
+        stops[index].position = stops[index].position.max(stops[index - 1].position);
+    }
+    Ok(stops)
+}
+
+/// Fills the buffer with the colors of the gradient at the positions computed from the pixel coordinates
+/// relative to the center.
+fn fill_with<F: Fn(f64, f64) -> f64>(buffer: &mut [u32], width: usize, height: usize, position: F, stops: &[Stop]) {
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    for (y, row) in buffer.chunks_exact_mut(width).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = color_at(stops, position(x as f64 + 0.5 - cx, y as f64 + 0.5 - cy));
+        }
+    }
+}
+
+fn color_at(stops: &[Stop], position: f64) -> u32 {
+    let next = stops.iter().position(|stop| stop.position > position);
+    let (from, to) = match next {
+        Some(0) => return stops[0].color,
+        Some(next) => (stops[next - 1], stops[next]),
+        None => return stops[stops.len() - 1].color
+    };
+    let t = (position - from.position) / (to.position - from.position);
+    let [_, r0, g0, b0] = from.color.to_be_bytes();
+    let [_, r1, g1, b1] = to.color.to_be_bytes();
+    let mix = |c0: u8, c1: u8| (c0 as f64 + (c1 as f64 - c0 as f64) * t).round() as u8;
+    images::from_u8_rgb(mix(r0, r1), mix(g0, g1), mix(b0, b1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, PI};
+    use super::*;
+
+    fn stops(args: &[&str]) -> Vec<(u32, f64)> {
+        parse_stops(args).unwrap().iter().map(|stop| (stop.color, stop.position)).collect()
+    }
+
+    #[test]
+    fn parses_directions() {
+        assert_eq!(parse_direction("90deg"), Some(Direction::Angle(FRAC_PI_2)));
+        assert_eq!(parse_direction("0.5turn"), Some(Direction::Angle(PI)));
+        assert_eq!(parse_direction("-1rad"), Some(Direction::Angle(-1.0)));
+        assert_eq!(parse_direction("to top"), Some(Direction::Angle(0.0)));
+        assert_eq!(parse_direction("to left"), Some(Direction::Angle(-FRAC_PI_2)));
+        assert_eq!(parse_direction("To Bottom Right"), Some(Direction::Corner(1.0, 1.0)));
+        for arg in ["red", "to", "to middle", "90", "90grad", "deg", "infdeg", "nandeg", "1e400deg"] {
+            assert_eq!(parse_direction(arg), None, "{}", arg);
+        }
+    }
+
+    #[test]
+    fn spreads_missing_stop_positions() {
+        assert_eq!(stops(&["red", "lime", "blue"]), [(0xff0000, 0.0), (0x00ff00, 0.5), (0x0000ff, 1.0)]);
+        assert_eq!(stops(&["red 20%", "lime", "blue 80%"]), [(0xff0000, 0.2), (0x00ff00, 0.5), (0x0000ff, 0.8)]);
+        // the positions don't go back
+        assert_eq!(stops(&["red 60%", "blue 30%"]), [(0xff0000, 0.6), (0x0000ff, 0.6)]);
+        assert_eq!(stops(&["rgb(0, 0, 0) 0%", "white 100%"]), [(0, 0.0), (0xffffff, 1.0)]);
+    }
+
+    #[test]
+    fn rejects_malformed_stops() {
+        for args in [&["red"][..], &[], &["red", "nocolor"], &["red", "blue x%"], &["red", "blue nan%"],
+                     &["red", "blue 1e400%"]] {
+            assert!(parse_stops(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn parses_backgrounds() {
+        assert!(matches!(Background::parse(" BLUR "), Ok(Background::Blur)));
+        assert!(matches!(Background::parse("linear-gradient(45deg, red, blue)"),
+                         Ok(Background::Linear { direction: Direction::Angle(_), .. })));
+        assert!(matches!(Background::parse("linear-gradient(red, rgb(0, 0, 255) 50%)"),
+                         Ok(Background::Linear { direction: Direction::Angle(angle), ref stops })
+                         if angle == PI && stops.len() == 2));
+        assert!(matches!(Background::parse("radial-gradient(circle, red, blue)"),
+                         Ok(Background::Radial { circle: true, .. })));
+        assert!(matches!(Background::parse("radial-gradient(red, blue)"), Ok(Background::Radial { circle: false, .. })));
+    }
+
+    #[test]
+    fn rejects_malformed_backgrounds() {
+        for spec in ["", "red", "blur()", "linear-gradient(red, blue", "linear-gradient()", "linear-gradient(red)",
+                     "linear-gradient(45deg)", "radial-gradient(circle, red)", "conic-gradient(red, blue)",
+                     "url(/nonexistent/tile.png)"] {
+            assert!(Background::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn interpolates_colors() {
+        let stops = parse_stops(&["black", "white"]).unwrap();
+        assert_eq!(color_at(&stops, -1.0), 0);
+        assert_eq!(color_at(&stops, 0.5), 0x808080);
+        assert_eq!(color_at(&stops, 2.0), 0xffffff);
+        // a hard edge between equal positions
+        let stops = parse_stops(&["red 50%", "blue 50%"]).unwrap();
+        assert_eq!(color_at(&stops, 0.49), 0xff0000);
+        assert_eq!(color_at(&stops, 0.5), 0x0000ff);
+    }
+}
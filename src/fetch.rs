@@ -0,0 +1,246 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{debug, warn};
+
+use crate::utils::Result;
+
+const HTTP_PREFIX: &str = "http://";
+const FILE_PREFIX: &str = "file://";
+
+/// Downloads images from http:// URLs, with an optional on-disk cache validated with ETags.
+#[derive(Debug, Clone)]
+pub struct Fetch {
+    /// The overall timeout of a single request.
+    pub timeout: Duration,
+    /// The maximum size of a downloaded image in bytes.
+    pub max_size: u64,
+    /// The cache directory, caching is disabled when `None`.
+    pub cache_dir: Option<PathBuf>
+}
+
+pub fn is_http_url(name: &str) -> bool {
+    name.get(0..HTTP_PREFIX.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(HTTP_PREFIX))
+}
+
+pub fn is_file_url(name: &str) -> bool {
+    name.get(0..FILE_PREFIX.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(FILE_PREFIX))
+}
+
+/// Returns the local path of a file:// URL, `None` unless the host part is empty or "localhost".
+pub fn file_url_path(name: &str) -> Option<PathBuf> {
+    let rest = name.get(FILE_PREFIX.len()..).filter(|_| is_file_url(name))?;
+    let (host, path) = rest.find('/').map_or((rest, ""), |index| rest.split_at(index));
+    if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+        return None
+    }
+    let path = percent_decode(path);
+    // file:///C:/path on Windows
+    if cfg!(windows) && path.get(2..3) == Some(":") {
+        return Some(PathBuf::from(&path[1..]))
+    }
+    Some(PathBuf::from(path))
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match (bytes[index], s.get(index + 1..index + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The FNV-1a hash of the URL, stable across program versions.
+fn cache_key(url: &str) -> String {
+    let hash = url.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+struct CacheEntry {
+    data_path: PathBuf,
+    etag_path: PathBuf
+}
+
+impl CacheEntry {
+    fn new(dir: &Path, url: &str) -> Self {
+        let key = cache_key(url);
+        CacheEntry {
+            data_path: dir.join(format!("{}.data", key)),
+            etag_path: dir.join(format!("{}.etag", key))
+        }
+    }
+
+    /// Returns the cached ETag if the entry belongs to the `url`.
+    fn etag(&self, url: &str) -> Option<String> {
+        let content = fs::read_to_string(&self.etag_path).ok()?;
+        let (etag, cached_url) = content.split_once('\n')?;
+        (cached_url == url && self.data_path.is_file()).then(|| etag.to_string())
+    }
+
+    fn read(&self) -> Result<Vec<u8>> {
+        Ok(fs::read(&self.data_path)?)
+    }
+
+    fn write(&self, url: &str, etag: &str, data: &[u8]) -> Result<()> {
+        if let Some(dir) = self.data_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.data_path.with_extension("tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &self.data_path)?;
+        fs::write(&self.etag_path, format!("{}\n{}", etag, url))?;
+        Ok(())
+    }
+}
+
+impl Fetch {
+    pub fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+        let entry = self.cache_dir.as_ref().map(|dir| CacheEntry::new(dir, url));
+        let etag = entry.as_ref().and_then(|entry| entry.etag(url));
+        let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+        let mut request = agent.get(url);
+        if let Some(etag) = etag.as_ref() {
+            debug!("cached {} etag: {}", url, etag);
+            request = request.set("If-None-Match", etag);
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(err) => {
+                return match entry.filter(|_| etag.is_some()) {
+                    Some(entry) => {
+                        warn!("fetching {} failed, using cached: {}", url, err);
+                        entry.read()
+                    }
+                    None => Err(err.into())
+                }
+            }
+        };
+        if response.status() == 304 {
+            if let Some(entry) = entry.as_ref() {
+                debug!("not modified: {}", url);
+                return entry.read()
+            }
+        }
+        if let Some(length) = response.header("Content-Length").and_then(|len| len.parse::<u64>().ok()) {
+            if length > self.max_size {
+                return Err("the image at the URL is too large".into())
+            }
+        }
+        let new_etag = response.header("ETag").map(str::to_string);
+        let mut data = Vec::new();
+        response.into_reader().take(self.max_size + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > self.max_size {
+            return Err("the image at the URL is too large".into())
+        }
+        debug!("fetched {} bytes from {}", data.len(), url);
+        if let (Some(entry), Some(new_etag)) = (entry, new_etag) {
+            if let Err(err) = entry.write(url, &new_etag, &data) {
+                warn!("caching {} failed: {}", url, err);
+            }
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Instant;
+    use super::*;
+
+    /// Serves the responses returned by the handler for the request headers, one connection each,
+    /// returns the URL of the server.
+    fn serve<F>(connections: usize, handler: F) -> String
+        where F: Fn(&str) -> Vec<u8> + Send + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                while reader.read_line(&mut request).unwrap() > 2 && !request.ends_with("\r\n\r\n") {}
+                let _ = stream.write_all(&handler(&request));
+            }
+        });
+        url
+    }
+
+    fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\n{}Connection: close\r\n\r\n", status, headers).into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    fn fetch(cache_dir: Option<PathBuf>) -> Fetch {
+        Fetch { timeout: Duration::from_secs(5), max_size: 16, cache_dir }
+    }
+
+    #[test]
+    fn revalidates_cached_images_with_etags() {
+        let cache_dir = std::env::temp_dir().join(format!("riv-fetch-test-{}", std::process::id()));
+        let requests = AtomicUsize::new(0);
+        let url = serve(2, move |request| {
+            match (requests.fetch_add(1, Ordering::SeqCst), request.contains("If-None-Match: \"v1\"")) {
+                (0, false) => response("200 OK", "ETag: \"v1\"\r\nContent-Length: 5\r\n", b"image"),
+                (1, true) => response("304 Not Modified", "", b""),
+                _ => response("200 OK", "Content-Length: 5\r\n", b"fresh")
+            }
+        });
+        let fetch = fetch(Some(cache_dir.clone()));
+        assert_eq!(fetch.fetch(&url).unwrap(), b"image");
+        // the second response has no body, the data comes from the cache
+        assert_eq!(fetch.fetch(&url).unwrap(), b"image");
+        fs::remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_images_over_the_max_size() {
+        let url = serve(1, |_| response("200 OK", "Content-Length: 17\r\n", &[0; 17]));
+        assert!(fetch(None).fetch(&url).is_err());
+        // without the length the download is cut off after the max size
+        let url = serve(1, |_| response("200 OK", "", &[0; 64]));
+        assert!(fetch(None).fetch(&url).is_err());
+        let url = serve(1, |_| response("200 OK", "", &[0; 16]));
+        assert_eq!(fetch(None).fetch(&url).unwrap().len(), 16);
+    }
+
+    #[test]
+    fn times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.png", listener.local_addr().unwrap());
+        // the connection is accepted by the backlog, but never answered
+        let fetch = Fetch { timeout: Duration::from_millis(200), ..fetch(None) };
+        let start = Instant::now();
+        assert!(fetch.fetch(&url).is_err());
+        assert!(start.elapsed() < Duration::from_secs(3));
+        drop(listener);
+    }
+
+    #[test]
+    fn file_urls_of_the_local_host() {
+        assert_eq!(file_url_path("file:///tmp/a%20b.png"), Some(PathBuf::from("/tmp/a b.png")));
+        assert_eq!(file_url_path("FILE://localhost/tmp/a.png"), Some(PathBuf::from("/tmp/a.png")));
+        assert_eq!(file_url_path("file://otherhost/etc/x"), None);
+        assert_eq!(file_url_path("file://localhost.example.com/etc/x"), None);
+        assert_eq!(file_url_path("/tmp/a.png"), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use log::{debug, warn};
+use image::{imageops, RgbImage};
+
+use crate::images::{self, Layout, ScaleMode};
+use crate::keys::Action;
+use crate::text::{self, Canvas};
+use crate::utils::Result;
+
+/// Thumbnails are decoded to fit in a square of this size.
+const THUMB_SIZE: u32 = 192;
+/// Cells are not made smaller than this, the sheet is scrolled instead.
+const MIN_CELL_SIZE: usize = 128;
+const CELL_PADDING: usize = 8;
+const MAX_THREADS: usize = 8;
+
+/// A contact sheet with the thumbnails of the images in a directory.
+pub struct Sheet {
+    /// The directory as given in the command.
+    pub dir: String,
+    files: Vec<PathBuf>,
+    /// The thumbnails decoded so far, `None` while decoding or if decoding failed.
+    thumbs: Vec<Option<RgbImage>>,
+    decoded: Receiver<(usize, RgbImage)>,
+    /// The background color.
+    pub color: u32,
+    /// The images opened from the sheet use the average color of their edges.
+    pub auto_color: bool,
+    captions: bool,
+    selected: usize,
+    /// The number of columns and the first visible row of the last rendered sheet.
+    columns: usize,
+    first_row: usize
+}
+
+impl Sheet {
+    /// Lists the images in the directory and starts decoding their thumbnails in parallel.
+    pub fn new(dir: &str, color: u32, auto_color: bool, captions: bool) -> Result<Self> {
+        let files = images::dir_images(Path::new(dir))?;
+        debug!("contact sheet of {} images in {}", files.len(), dir);
+        let (send, decoded) = channel();
+        let shared = Arc::new(files.clone());
+        let next = Arc::new(AtomicUsize::new(0));
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get()).min(MAX_THREADS);
+        for _ in 0..threads.min(files.len()) {
+            let (files, next, send) = (shared.clone(), next.clone(), send.clone());
+            thread::spawn(move || {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let file = match files.get(index) {
+                        Some(file) => file,
+                        None => break
+                    };
+                    match images::load_image(file, false) {
+                        Ok((img, _)) => {
+                            let (width, height) = ScaleMode::Down.scaled_size(img.dimensions(), (THUMB_SIZE, THUMB_SIZE));
+                            // the sheet was closed
+                            if send.send((index, imageops::thumbnail(&img, width, height))).is_err() {
+                                break
+                            }
+                        }
+                        Err(err) => warn!("loading {:?} failed: {}", file, err)
+                    }
+                }
+            });
+        }
+        Ok(Sheet {
+            dir: dir.to_string(),
+            thumbs: vec![None; files.len()],
+            files,
+            decoded,
+            color,
+            auto_color,
+            captions,
+            selected: 0,
+            columns: 1,
+            first_row: 0
+        })
+    }
+
+    /// Takes the thumbnails decoded in the meantime, returns whether there were any.
+    pub fn poll(&mut self) -> bool {
+        let mut any = false;
+        for (index, thumb) in self.decoded.try_iter() {
+            self.thumbs[index] = Some(thumb);
+            any = true;
+        }
+        any
+    }
+
+    pub fn selected_file(&self) -> Option<&Path> {
+        self.files.get(self.selected).map(PathBuf::as_path)
+    }
+
+    /// Selects the file, if it's in the sheet.
+    pub fn select_file(&mut self, name: &str) {
+        let path = Path::new(name);
+        if let Some(index) = self.files.iter().position(|file| file == path || file.file_name() == path.file_name()) {
+            self.selected = index;
+        }
+    }
+
+    /// Moves the selection, returns whether the action was a navigation one.
+    pub fn navigate(&mut self, action: Action) -> bool {
+        let last = self.files.len().saturating_sub(1);
+        self.selected = match action {
+            Action::Next => (self.selected + 1).min(last),
+            Action::Prev => self.selected.saturating_sub(1),
+            Action::Down => (self.selected + self.columns).min(last),
+            Action::Up => self.selected.saturating_sub(self.columns),
+            Action::First => 0,
+            Action::Last => last,
+            _ => return false
+        };
+        true
+    }
+
+    /// Renders the sheet into a window buffer, scrolled to the selected thumbnail.
+    pub fn render(&mut self, width: usize, height: usize) -> Vec<u32> {
+        let color = self.color;
+        let mut buffer = vec![color; width * height];
+        let count = self.files.len();
+        let fg = contrast(color);
+        let mut canvas = Canvas { buf: &mut buffer, width, height };
+        if count == 0 {
+            let message = format!("no images in {}", self.dir);
+            let (text_width, text_height) = text::text_size(&message, 1);
+            canvas.draw_text((width as isize - text_width as isize) / 2, (height as isize - text_height as isize) / 2,
+                             &message, fg, 1);
+            return buffer
+        }
+        // near square cells fitting all the thumbnails, unless they would be too small
+        let mut columns = ((count as f64 * width as f64 / height.max(1) as f64).sqrt().ceil() as usize).clamp(1, count);
+        let mut rows = count.div_ceil(columns);
+        if (width / columns).min(height / rows) < MIN_CELL_SIZE {
+            columns = (width / MIN_CELL_SIZE).max(1);
+            rows = (height / MIN_CELL_SIZE).max(1);
+        }
+        self.columns = columns;
+        let selected_row = self.selected / columns;
+        if selected_row < self.first_row {
+            self.first_row = selected_row;
+        }
+        else if selected_row >= self.first_row + rows {
+            self.first_row = selected_row + 1 - rows;
+        }
+        let (cell_width, cell_height) = (width / columns, height / rows);
+        let caption_height = if self.captions { text::GLYPH_SIZE + CELL_PADDING / 2 } else { 0 };
+        let thumb_width = cell_width.saturating_sub(2 * CELL_PADDING);
+        let thumb_height = cell_height.saturating_sub(2 * CELL_PADDING + caption_height);
+        let first = self.first_row * columns;
+        for index in first..count.min(first + rows * columns) {
+            let (x, y) = ((index - first) % columns * cell_width, (index - first) / columns * cell_height);
+            if index == self.selected {
+                canvas.fill_rect(x as isize, y as isize, cell_width, cell_height, fg, 48);
+            }
+            if let Some(thumb) = self.thumbs[index].as_ref().filter(|_| thumb_width * thumb_height != 0) {
+                let bg = canvas.buf[y * width + x];
+                let mut part = vec![bg; thumb_width * thumb_height];
+                images::compose_into(thumb, &Layout::new(ScaleMode::Down), Some(bg),
+                                     thumb_width as u32, thumb_height as u32, &mut part);
+                images::copy_into(&part, thumb_width, x + CELL_PADDING, y + CELL_PADDING, width, height, canvas.buf);
+            }
+            if self.captions {
+                let name = self.files[index].file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+                let max_chars = thumb_width / text::GLYPH_SIZE;
+                let caption: String = if name.chars().count() > max_chars {
+                    name.chars().take(max_chars.saturating_sub(1)).chain(Some('~')).collect()
+                }
+                else {
+                    name.into_owned()
+                };
+                let (text_width, _) = text::text_size(&caption, 1);
+                canvas.draw_text((x + cell_width.saturating_sub(text_width) / 2) as isize,
+                                 (y + cell_height) as isize - (CELL_PADDING + text::GLYPH_SIZE) as isize,
+                                 &caption, fg, 1);
+            }
+        }
+        buffer
+    }
+}
+
+/// Returns black or white, whichever is more visible over the color.
+fn contrast(color: u32) -> u32 {
+    let [_, r, g, b] = color.to_be_bytes();
+    if r as u32 * 299 + g as u32 * 587 + b as u32 * 114 > 128_000 { 0 } else { 0xffffff }
+}
@@ -0,0 +1,111 @@
+use std::fmt::Write;
+use std::path::Path;
+use image::RgbImage;
+
+use crate::images::{Layout, Metadata};
+use crate::remote::Display;
+use crate::text::{self, Canvas};
+use crate::utils;
+
+const TEXT_COLOR: u32 = 0xffffff;
+/// The opacity of the background behind the text.
+const BACKGROUND_ALPHA: u8 = 160;
+
+/// The information about the image shown in the whole window.
+pub struct Info<'a> {
+    /// The file name, "-" for images read from the standard input or sent by the remote processes.
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub meta: Option<&'a Metadata>,
+    /// The scale factors of the image laid out in the window, including the zoom.
+    pub scale: (f64, f64),
+    pub zoom: f64
+}
+
+impl<'a> Info<'a> {
+    pub fn new(display: &'a Display, img: &RgbImage, layout: &Layout, buf_size: (usize, usize)) -> Self {
+        let name = Path::new(&display.name).file_name().map_or_else(|| "-".into(), |name| name.to_string_lossy());
+        Info {
+            name: name.into_owned(),
+            width: img.width(),
+            height: img.height(),
+            meta: display.meta.as_ref(),
+            scale: layout.factors(img.dimensions(), (buf_size.0 as u32, buf_size.1 as u32)),
+            zoom: layout.zoom
+        }
+    }
+
+    /// Returns the lines of text shown by the overlay.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![self.name.clone()];
+        let mut size = format!("{}x{}", self.width, self.height);
+        if let Some(meta) = self.meta {
+            for info in [&meta.format, &meta.color_type].iter().filter(|info| !info.is_empty()) {
+                write!(size, " {}", info).unwrap();
+            }
+        }
+        lines.push(size);
+        if let Some(meta) = self.meta {
+            lines.push(format!("{} decoded in {:.1} ms", format_size(meta.file_size),
+                               meta.decode_time.as_secs_f64() * 1000.0));
+        }
+        let (fx, fy) = self.scale;
+        let mut scale = format!("scale {}%", percent(fx));
+        if percent(fx) != percent(fy) {
+            write!(scale, " x {}%", percent(fy)).unwrap();
+        }
+        if self.zoom != 1.0 {
+            write!(scale, " zoom {}%", percent(self.zoom)).unwrap();
+        }
+        lines.push(scale);
+        if let Some(meta) = self.meta {
+            lines.extend(meta.exif.iter().map(|(_, value)| value.clone()));
+        }
+        lines
+    }
+
+    /// Returns the information as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"name\": {}, \"width\": {}, \"height\": {}",
+                               utils::json_string(&self.name), self.width, self.height);
+        if let Some(meta) = self.meta {
+            write!(json, ", \"format\": {}, \"color_type\": {}, \"file_size\": {}, \"decode_ms\": {:.1}",
+                   utils::json_string(&meta.format), utils::json_string(&meta.color_type), meta.file_size,
+                   meta.decode_time.as_secs_f64() * 1000.0).unwrap();
+        }
+        write!(json, ", \"scale\": [{}, {}], \"zoom\": {}", self.scale.0, self.scale.1, self.zoom).unwrap();
+        if let Some(meta) = self.meta {
+            let exif: Vec<String> = meta.exif.iter().map(|(label, value)| {
+                format!("{}: {}", utils::json_string(label), utils::json_string(value))
+            }).collect();
+            write!(json, ", \"exif\": {{{}}}", exif.join(", ")).unwrap();
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Draws the lines of text over a dimmed background in the top left corner.
+pub fn draw(lines: &[String], width: usize, height: usize, buffer: &mut [u32]) {
+    let margin = text::GLYPH_SIZE / 2;
+    let text_width = lines.iter().map(|line| text::text_size(line, 1).0).max().unwrap_or(0);
+    let line_height = text::GLYPH_SIZE + margin;
+    let mut canvas = Canvas { buf: buffer, width, height };
+    canvas.fill_rect(0, 0, text_width + 2 * margin, lines.len() * line_height + margin, 0, BACKGROUND_ALPHA);
+    for (index, line) in lines.iter().enumerate() {
+        canvas.draw_text(margin as isize, (margin + index * line_height) as isize, line, TEXT_COLOR, 1);
+    }
+}
+
+fn percent(factor: f64) -> f64 {
+    (factor * 1000.0).round() / 10.0
+}
+
+fn format_size(size: u64) -> String {
+    match size {
+        0..=9_999 => format!("{} B", size),
+        10_000..=9_999_999 => format!("{:.1} kB", size as f64 / 1e3),
+        _ => format!("{:.1} MB", size as f64 / 1e6)
+    }
+}
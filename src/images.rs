@@ -1,85 +1,436 @@
-use std::path::Path;
+use core::fmt;
+use core::str::FromStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Seek};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use log::debug;
-use image::{io::Reader as ImageReader, GenericImageView, Pixel, ColorType, RgbImage, PixelWithColorType};
+use exif::{In, Tag};
+use image::{io::Reader as ImageReader, GenericImageView, Pixel, ColorType, ImageFormat, RgbImage, PixelWithColorType};
+use image::imageops::{self, FilterType};
 use num_traits::cast::ToPrimitive;
 
+use crate::fetch::{self, Fetch};
 use crate::utils::Result;
 
-pub fn load_image_center_into<P: AsRef<Path>>(
-    name: P,
-    bgpixel: u32,
+/// Loads images from files, URLs or memory in their original size.
+#[derive(Debug, Clone)]
+pub struct Loader {
+    pub with_info: bool,
+    pub fetch: Fetch
+}
+
+/// What is known about a loaded image besides its pixels.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    /// The file format, e.g. "JPEG", or the raw frame format.
+    pub format: String,
+    /// The color type of the decoded image, e.g. "Rgb8".
+    pub color_type: String,
+    /// The size of the file or the data in bytes.
+    pub file_size: u64,
+    pub decode_time: Duration,
+    /// The EXIF highlights: "camera", "exposure" and "date", if present.
+    pub exif: Vec<(&'static str, String)>,
+    /// The average color of the image border, used as the background color with `--color auto`.
+    pub edge_color: u32
+}
+
+impl Loader {
+    /// Loads an image from a file path, a file:// or an http:// URL.
+    pub fn load(&self, name: &str) -> Result<(RgbImage, Metadata)> {
+        if fetch::is_http_url(name) {
+            if self.with_info {
+                println!("URL: {}", name);
+            }
+            let data = self.fetch.fetch(name)?;
+            load_image_from_memory(&data, self.with_info)
+        }
+        else if fetch::is_file_url(name) {
+            let path = fetch::file_url_path(name).ok_or("file:// URLs must refer to the local host")?;
+            load_image(path, self.with_info)
+        }
+        else {
+            load_image(name, self.with_info)
+        }
+    }
+
+    pub fn load_from_memory(&self, data: &[u8]) -> Result<(RgbImage, Metadata)> {
+        load_image_from_memory(data, self.with_info)
+    }
+
+    /// Copies an uncompressed frame without decoding.
+    pub fn load_raw(&self, data: &[u8], format: &RawFormat) -> Result<(RgbImage, Metadata)> {
+        let start = Instant::now();
+        let img = raw_to_image(data, format)?;
+        let meta = Metadata {
+            format: format!("raw {}", format),
+            color_type: format!("{:?}", ColorType::Rgb8),
+            file_size: data.len() as u64,
+            decode_time: start.elapsed(),
+            exif: Vec::new(),
+            edge_color: edge_color(&img)
+        };
+        Ok((img, meta))
+    }
+}
+
+/// How images are fitted into the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    /// Images are shown in their original size, cropped according to the alignment if larger than the window.
+    #[default]
+    None,
+    /// Images are scaled to fit in the window, preserving the aspect ratio.
+    Fit,
+    /// Like `Fit`, but images smaller than the window are not enlarged.
+    Down,
+    /// Images are scaled to cover the whole window, preserving the aspect ratio and cropped.
+    Fill,
+    /// Images are scaled to the window size, ignoring the aspect ratio.
+    Stretch,
+    /// Images are enlarged by the largest whole factor fitting in the window, keeping the pixels sharp.
+    Integer
+}
+
+impl ScaleMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ScaleMode::None => "none",
+            ScaleMode::Fit => "fit",
+            ScaleMode::Down => "down",
+            ScaleMode::Fill => "fill",
+            ScaleMode::Stretch => "stretch",
+            ScaleMode::Integer => "integer"
+        }
+    }
+
+    /// Returns the size of the image scaled for the window.
+    pub fn scaled_size(self, (img_width, img_height): (u32, u32), (buf_width, buf_height): (u32, u32)) -> (u32, u32) {
+        let fit = (buf_width as f64 / img_width as f64).min(buf_height as f64 / img_height as f64);
+        let fill = (buf_width as f64 / img_width as f64).max(buf_height as f64 / img_height as f64);
+        let ratio = match self {
+            ScaleMode::None => return (img_width, img_height),
+            ScaleMode::Stretch => return (buf_width, buf_height),
+            ScaleMode::Fit => fit,
+            ScaleMode::Down => fit.min(1.0),
+            ScaleMode::Fill => fill,
+            ScaleMode::Integer => fit.floor().max(1.0)
+        };
+        (((img_width as f64 * ratio).round() as u32).max(1),
+         ((img_height as f64 * ratio).round() as u32).max(1))
+    }
+}
+
+impl FromStr for ScaleMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "none" => ScaleMode::None,
+            "fit" => ScaleMode::Fit,
+            "down" => ScaleMode::Down,
+            "fill" => ScaleMode::Fill,
+            "stretch" => ScaleMode::Stretch,
+            "integer" => ScaleMode::Integer,
+            _ => return Err("scale mode must be one of: none, fit, down, fill, stretch, integer")
+        })
+    }
+}
+
+/// Where images are placed in the window, the anchor point of the image is aligned with the same point
+/// of the window, images larger than the window are cropped on the opposite sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight
+}
+
+impl Align {
+    const NAMES: [(&'static str, Align); 9] = [
+        ("top-left", Align::TopLeft),
+        ("top", Align::Top),
+        ("top-right", Align::TopRight),
+        ("left", Align::Left),
+        ("center", Align::Center),
+        ("right", Align::Right),
+        ("bottom-left", Align::BottomLeft),
+        ("bottom", Align::Bottom),
+        ("bottom-right", Align::BottomRight)
+    ];
+
+    pub fn name(self) -> &'static str {
+        Align::NAMES.iter().find(|&&(_, align)| align == self).map(|&(name, _)| name).unwrap()
+    }
+
+    /// Returns the horizontal and vertical fractions of the free space placed before the image.
+    pub fn fractions(self) -> (f64, f64) {
+        match self {
+            Align::TopLeft => (0.0, 0.0),
+            Align::Top => (0.5, 0.0),
+            Align::TopRight => (1.0, 0.0),
+            Align::Left => (0.0, 0.5),
+            Align::Center => (0.5, 0.5),
+            Align::Right => (1.0, 0.5),
+            Align::BottomLeft => (0.0, 1.0),
+            Align::Bottom => (0.5, 1.0),
+            Align::BottomRight => (1.0, 1.0)
+        }
+    }
+}
+
+impl FromStr for Align {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        Align::NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(s))
+                    .map(|&(_, align)| align)
+                    .ok_or("align must be one of: top-left, top, top-right, left, center, right, \
+                            bottom-left, bottom, bottom-right")
+    }
+}
+
+/// How images are laid out in the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub scale: ScaleMode,
+    pub align: Align,
+    /// The distance the aligned image is moved by, in window pixels.
+    pub offset: (f64, f64),
+    /// The zoom factor applied after scaling.
+    pub zoom: f64,
+    /// The distance the aligned image is moved by in the opposite direction, in image pixels.
+    pub pan: (f64, f64)
+}
+
+impl Layout {
+    pub const MIN_ZOOM: f64 = 1.0 / 16.0;
+    pub const MAX_ZOOM: f64 = 16.0;
+
+    pub fn new(scale: ScaleMode) -> Self {
+        Layout { scale, align: Align::Center, offset: (0.0, 0.0), zoom: 1.0, pan: (0.0, 0.0) }
+    }
+
+    pub fn set_zoom(&mut self, zoom: f64) {
+        self.zoom = zoom.clamp(Self::MIN_ZOOM, Self::MAX_ZOOM);
+    }
+
+    /// Returns the horizontal and vertical scale factors of the image laid out in the buffer.
+    pub fn factors(&self, (img_width, img_height): (u32, u32), buf_size: (u32, u32)) -> (f64, f64) {
+        let (width, height) = self.scale.scaled_size((img_width, img_height), buf_size);
+        (((width as f64 * self.zoom).round()).max(1.0) / img_width as f64,
+         ((height as f64 * self.zoom).round()).max(1.0) / img_height as f64)
+    }
+
+    /// Returns the position of the top left image corner in the buffer and the scale factors.
+    pub fn placement(&self, img_size: (u32, u32), buf_size: (u32, u32)) -> ((f64, f64), (f64, f64)) {
+        let (fx, fy) = self.factors(img_size, buf_size);
+        let (x, y) = self.aligned(img_size, buf_size, (fx, fy));
+        ((x - self.pan.0 * fx, y - self.pan.1 * fy), (fx, fy))
+    }
+
+    /// Shows the image point given in image pixels in the window center.
+    pub fn set_center(&mut self, (x, y): (f64, f64), img_size: (u32, u32), buf_size: (u32, u32)) {
+        let (fx, fy) = self.factors(img_size, buf_size);
+        let (left, top) = self.aligned(img_size, buf_size, (fx, fy));
+        self.pan = ((left - buf_size.0 as f64 / 2.0) / fx + x, (top - buf_size.1 as f64 / 2.0) / fy + y);
+        self.clamp_pan(img_size, buf_size);
+    }
+
+    /// Moves the image by the distance given in buffer pixels.
+    pub fn pan_by(&mut self, (dx, dy): (f64, f64), img_size: (u32, u32), buf_size: (u32, u32)) {
+        let (fx, fy) = self.factors(img_size, buf_size);
+        self.pan = (self.pan.0 - dx / fx, self.pan.1 - dy / fy);
+        self.clamp_pan(img_size, buf_size);
+    }
+
+    /// Changes the zoom keeping the image point under the buffer point `at` in place.
+    pub fn zoom_at(&mut self, zoom: f64, at: (f64, f64), img_size: (u32, u32), buf_size: (u32, u32)) {
+        let ((left, top), (fx, fy)) = self.placement(img_size, buf_size);
+        let point = ((at.0 - left) / fx, (at.1 - top) / fy);
+        self.set_zoom(zoom);
+        let (fx, fy) = self.factors(img_size, buf_size);
+        let (left, top) = self.aligned(img_size, buf_size, (fx, fy));
+        self.pan = ((left - at.0) / fx + point.0, (top - at.1) / fy + point.1);
+        self.clamp_pan(img_size, buf_size);
+    }
+
+    /// Returns the position of the top left image corner in the buffer when not panned.
+    fn aligned(&self, img_size: (u32, u32), buf_size: (u32, u32), (fx, fy): (f64, f64)) -> (f64, f64) {
+        let (ax, ay) = self.align.fractions();
+        ((buf_size.0 as f64 - img_size.0 as f64 * fx) * ax + self.offset.0,
+         (buf_size.1 as f64 - img_size.1 as f64 * fy) * ay + self.offset.1)
+    }
+
+    /// Keeps the window center within the image, unless it's outside of the image when not panned.
+    fn clamp_pan(&mut self, img_size: (u32, u32), buf_size: (u32, u32)) {
+        let (fx, fy) = self.factors(img_size, buf_size);
+        let (left, top) = self.aligned(img_size, buf_size, (fx, fy));
+        let clamp = |pan: f64, aligned: f64, factor: f64, size: u32, buf_size: u32| {
+            let (center, size) = (buf_size as f64 / 2.0, size as f64 * factor);
+            pan.clamp((aligned - center.max(aligned)) / factor, (aligned - (center - size).min(aligned)) / factor)
+        };
+        self.pan = (clamp(self.pan.0, left, fx, img_size.0, buf_size.0),
+                    clamp(self.pan.1, top, fy, img_size.1, buf_size.1));
+    }
+}
+
+/// Scales the image according to the `layout` and draws it in the buffer, filled with `bgpixel` first if given.
+pub fn compose_into(
+    img: &RgbImage,
+    layout: &Layout,
+    bgpixel: Option<u32>,
     buf_width: u32,
     buf_height: u32,
-    buf: &mut [u32],
-    with_info: bool
-  ) -> Result<()>
+    buf: &mut [u32]
+  )
 {
-  let img = load_image(name, buf_width, buf_height, with_info)?;
-  center_image_into(&img, bgpixel, buf_width, buf_height, buf);
-  Ok(())
+    let (img_width, img_height) = img.dimensions();
+    let ((left, top), (fx, fy)) = layout.placement((img_width, img_height), (buf_width, buf_height));
+    if (fx, fy) == (1.0, 1.0) {
+        place_image_into(img, bgpixel, left.round() as i64, top.round() as i64, buf_width, buf_height, buf);
+        return
+    }
+    // only the visible part of the image is scaled
+    let src_x = ((-left / fx).floor().max(0.0) as u32).min(img_width);
+    let src_y = ((-top / fy).floor().max(0.0) as u32).min(img_height);
+    let src_right = (((buf_width as f64 - left) / fx).ceil().max(0.0) as u32).min(img_width);
+    let src_bottom = (((buf_height as f64 - top) / fy).ceil().max(0.0) as u32).min(img_height);
+    if src_x >= src_right || src_y >= src_bottom {
+        if let Some(bgpixel) = bgpixel {
+            buf.fill(bgpixel);
+        }
+        return
+    }
+    let (src_width, src_height) = (src_right - src_x, src_bottom - src_y);
+    let width = ((src_width as f64 * fx).round() as u32).max(1);
+    let height = ((src_height as f64 * fy).round() as u32).max(1);
+    debug!("scale {}x{}+{}+{} -> {}x{}", src_width, src_height, src_x, src_y, width, height);
+    let part = imageops::crop_imm(img, src_x, src_y, src_width, src_height);
+    let filter = if layout.scale == ScaleMode::Integer { FilterType::Nearest } else { FilterType::Triangle };
+    let scaled = imageops::resize(&*part, width, height, filter);
+    place_image_into(&scaled, bgpixel, (left + src_x as f64 * fx).round() as i64,
+                     (top + src_y as f64 * fy).round() as i64, buf_width, buf_height, buf);
 }
 
-pub fn center_image_into(
+/// Fills the buffer with the background, if given, and draws the image with its top left corner at `x`, `y`,
+/// the parts outside of the buffer are cropped.
+pub fn place_image_into(
     img: &RgbImage,
-    bgpixel: u32,
+    bgpixel: Option<u32>,
+    x: i64,
+    y: i64,
     buf_width: u32,
     buf_height: u32,
     buf: &mut [u32]
   )
 {
     let (img_width, img_height) = img.dimensions();
-  
-    assert!(img_width <= buf_width && img_height <= buf_height);
-  
-    let margin_left = (buf_width - img_width) as usize / 2;
-    let margin_right = (buf_width - img_width) as usize - margin_left;
-    let margin_top = (buf_height - img_height) / 2;
-    let margin_bot = buf_height - img_height - margin_top;
-  
-    let yoffset_top = margin_top as usize * buf_width as usize;
-    let yoffset_bot = margin_bot as usize * buf_width as usize;
-  
-    let mut tgt_iter = buf.iter_mut();
-  
-    for tgt in tgt_iter.by_ref().take(yoffset_top) {
-        *tgt = bgpixel;
-    }
-  
-    for pixels in img.rows() {
-        for tgt in tgt_iter.by_ref().take(margin_left) {
-            *tgt = bgpixel;
-        }
-        for (src, tgt) in pixels.zip(tgt_iter.by_ref()) {
-            if let Some(pixel) = src.into_rgb32() {
+    let (src_x, src_y) = ((-x).clamp(0, img_width as i64) as u32, (-y).clamp(0, img_height as i64) as u32);
+    let tgt_x = x.clamp(0, buf_width as i64) as usize;
+    let tgt_y = y.clamp(0, buf_height as i64) as usize;
+    let width = (img_width - src_x).min(buf_width - tgt_x as u32);
+    let height = (img_height - src_y).min(buf_height - tgt_y as u32);
+
+    if let Some(bgpixel) = bgpixel {
+        buf.fill(bgpixel);
+    }
+
+    for row in 0..height {
+        let offset = (tgt_y + row as usize) * buf_width as usize + tgt_x;
+        let tgt_row = &mut buf[offset..offset + width as usize];
+        for (x, tgt) in (src_x..src_x + width).zip(tgt_row.iter_mut()) {
+            if let Some(pixel) = img.get_pixel(x, src_y + row).into_rgb32() {
                 *tgt = pixel;
             }
         }
-        for tgt in tgt_iter.by_ref().take(margin_right) {
-            *tgt = bgpixel;
-        }
     }
-  
-    for tgt in tgt_iter.take(yoffset_bot) {
-        *tgt = bgpixel;
+}
+
+/// Copies the `part` buffer into the buffer with its top left corner at `x`, `y`, cropped to the buffer.
+pub fn copy_into(
+    part: &[u32],
+    part_width: usize,
+    x: usize,
+    y: usize,
+    buf_width: usize,
+    buf_height: usize,
+    buf: &mut [u32]
+  )
+{
+    let part_height = part.len().checked_div(part_width).unwrap_or(0);
+    let width = part_width.min(buf_width.saturating_sub(x));
+    for row in 0..part_height.min(buf_height.saturating_sub(y)) {
+        let offset = (y + row) * buf_width + x;
+        buf[offset..offset + width].copy_from_slice(&part[row * part_width..row * part_width + width]);
     }
 }
 
+/// Returns the image files in the directory of the `path`, sorted by name.
+pub fn sibling_images(path: &Path) -> io::Result<Vec<PathBuf>> {
+    dir_images(path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new(".")))
+}
+
+/// Returns the image files in the directory, sorted by name.
+pub fn dir_images(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && ImageFormat::from_path(path).is_ok())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
 pub fn load_image<P: AsRef<Path>>(
     name: P,
-    max_width: u32,
-    max_height: u32,
     with_info: bool
-  ) -> Result<RgbImage>
+  ) -> Result<(RgbImage, Metadata)>
 {
+    let path = name.as_ref();
     if with_info {
-        println!("File: {:?}", name.as_ref());
+        println!("File: {:?}", path);
     }
-    let reader = ImageReader::open(name)?
-                             .with_guessed_format()?;
+    let reader = ImageReader::open(path)?;
+    let (img, mut meta) = decode_image(reader, with_info)?;
+    meta.file_size = fs::metadata(path)?.len();
+    meta.exif = read_exif(&mut BufReader::new(File::open(path)?));
+    Ok((img, meta))
+}
+
+pub fn load_image_from_memory(
+    data: &[u8],
+    with_info: bool
+  ) -> Result<(RgbImage, Metadata)>
+{
+    if with_info {
+        println!("Data: {} bytes", data.len());
+    }
+    let reader = ImageReader::new(Cursor::new(data));
+    let (img, mut meta) = decode_image(reader, with_info)?;
+    meta.file_size = data.len() as u64;
+    meta.exif = read_exif(&mut Cursor::new(data));
+    Ok((img, meta))
+}
+
+fn decode_image<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    with_info: bool
+  ) -> Result<(RgbImage, Metadata)>
+{
+    let reader = reader.with_guessed_format()?;
     let format = reader.format();
-    let mut img = reader.decode()?;
+    let start = Instant::now();
+    let img = reader.decode()?;
 
-    let (mut img_width, mut img_height) = img.dimensions();
+    let (img_width, img_height) = img.dimensions();
     let color = img.color();
     if with_info {
         println!("Format: {:?} {}x{} {:?} bits/pixel: {} channels: {}",
@@ -90,24 +441,185 @@ pub fn load_image<P: AsRef<Path>>(
             color.bits_per_pixel(),
             color.channel_count());
     }
-    if img_width > max_width || img_height > max_height {
-        let width = img_width.min(max_width);
-        let height = img_height.min(max_height);
-        let x = if img_width > max_width {
-            (img_width - max_width) / 2
+
+    let img = img.into_rgb8();
+    let meta = Metadata {
+        format: format.map(|format| format!("{:?}", format).to_uppercase()).unwrap_or_default(),
+        color_type: format!("{:?}", color),
+        decode_time: start.elapsed(),
+        edge_color: edge_color(&img),
+        ..Metadata::default()
+    };
+    Ok((img, meta))
+}
+
+/// Returns the average color of the pixels along the image border, one percent of the image size deep.
+pub fn edge_color(img: &RgbImage) -> u32 {
+    let (width, height) = img.dimensions();
+    let depth = (width.min(height) / 100).max(1);
+    let (mut sum, mut count) = ([0u64; 3], 0u64);
+    let mut add = |x, y| {
+        let pixel: &image::Rgb<u8> = img.get_pixel(x, y);
+        for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+            *sum += channel as u64;
         }
-        else { 0 };
-        let y = if img_height > max_height {
-            (img_height - max_height) / 2
+        count += 1;
+    };
+    for y in 0..height {
+        if y < depth || y + depth >= height {
+            (0..width).for_each(|x| add(x, y));
+        }
+        else {
+            (0..depth).chain(width - depth..width).for_each(|x| add(x, y));
+        }
+    }
+    match count {
+        0 => 0,
+        _ => from_u8_rgb((sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8)
+    }
+}
+
+/// Reads the camera, the exposure and the date the photo was taken from the EXIF data, if there is any.
+fn read_exif<R: BufRead + Seek>(reader: &mut R) -> Vec<(&'static str, String)> {
+    let exif = match exif::Reader::new().read_from_container(reader) {
+        Ok(exif) => exif,
+        Err(..) => return Vec::new()
+    };
+    let value = |tag| exif.get_field(tag, In::PRIMARY).map(|field| {
+        field.display_value().with_unit(&exif).to_string().trim_matches('"').trim().to_string()
+    }).filter(|value| !value.is_empty());
+    let camera = match (value(Tag::Make), value(Tag::Model)) {
+        (Some(make), Some(model)) if !model.starts_with(&make) => Some(format!("{} {}", make, model)),
+        (make, model) => model.or(make)
+    };
+    let exposure = [value(Tag::ExposureTime), value(Tag::FNumber),
+                    value(Tag::PhotographicSensitivity).map(|iso| format!("ISO {}", iso)),
+                    value(Tag::FocalLength)];
+    let exposure = exposure.iter().flatten().map(String::as_str).collect::<Vec<_>>().join(" ");
+    let date = value(Tag::DateTimeOriginal).or_else(|| value(Tag::DateTime));
+    [("camera", camera), ("exposure", Some(exposure).filter(|exposure| !exposure.is_empty())), ("date", date)]
+        .iter().filter_map(|(label, value)| value.clone().map(|value| (*label, value)))
+        .collect()
+}
+
+/// The pixel layout of raw frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgb,
+    Rgba,
+    Bgr,
+    Bgra,
+    Gray
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgb|PixelFormat::Bgr => 3,
+            PixelFormat::Rgba|PixelFormat::Bgra => 4,
+            PixelFormat::Gray => 1
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Rgb => "rgb",
+            PixelFormat::Rgba => "rgba",
+            PixelFormat::Bgr => "bgr",
+            PixelFormat::Bgra => "bgra",
+            PixelFormat::Gray => "gray"
+        }
+    }
+
+    #[inline]
+    fn to_rgb(self, pixel: &[u8]) -> [u8; 3] {
+        match self {
+            PixelFormat::Rgb|PixelFormat::Rgba => [pixel[0], pixel[1], pixel[2]],
+            PixelFormat::Bgr|PixelFormat::Bgra => [pixel[2], pixel[1], pixel[0]],
+            PixelFormat::Gray => [pixel[0]; 3]
+        }
+    }
+}
+
+/// Raw frames are limited like the transferred data, so the images can be allocated.
+const MAX_RAW_FRAME_SIZE: usize = 256 << 20;
+
+/// The layout of an uncompressed frame: "format:WIDTHxHEIGHT[:stride]",
+/// where format is one of: rgb, rgba, bgr, bgra, gray and stride is the number of bytes per row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFormat {
+    pub pixel: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    pub stride: usize
+}
+
+impl RawFormat {
+    /// The number of bytes of a single frame.
+    pub fn frame_size(&self) -> usize {
+        self.stride * self.height as usize
+    }
+}
+
+impl FromStr for RawFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let pixel = match parts.next().unwrap_or("").to_ascii_lowercase().as_str() {
+            "rgb" => PixelFormat::Rgb,
+            "rgba" => PixelFormat::Rgba,
+            "bgr" => PixelFormat::Bgr,
+            "bgra" => PixelFormat::Bgra,
+            "gray"|"grey" => PixelFormat::Gray,
+            _ => return Err("unknown raw pixel format")
+        };
+        let (width, height) = parts.next().and_then(|size| size.split_once('x'))
+                                   .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                                   .filter(|&(w, h)| w != 0 && h != 0)
+                                   .ok_or("raw frame size must be WIDTHxHEIGHT")?;
+        let row_size = width as usize * pixel.bytes_per_pixel();
+        let stride = match parts.next() {
+            Some(stride) => stride.parse().ok().filter(|&stride| stride >= row_size)
+                                  .ok_or("raw frame stride must be at least the size of a row")?,
+            None => row_size
+        };
+        if parts.next().is_some() {
+            return Err("invalid raw frame format");
+        }
+        // the stride is at least the row size, so this limits the number of pixels as well
+        if stride.checked_mul(height as usize).is_none_or(|size| size > MAX_RAW_FRAME_SIZE) {
+            return Err("raw frame is too large, the limit is 256 MiB");
+        }
+        Ok(RawFormat { pixel, width, height, stride })
+    }
+}
+
+impl fmt::Display for RawFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}x{}:{}", self.pixel.name(), self.width, self.height, self.stride)
+    }
+}
+
+/// Converts an uncompressed frame to an image.
+pub fn raw_to_image(data: &[u8], format: &RawFormat) -> Result<RgbImage> {
+    let RawFormat { pixel, width, height, stride } = *format;
+    let bpp = pixel.bytes_per_pixel();
+    let size = stride.checked_mul(height as usize - 1).and_then(|size| size.checked_add(width as usize * bpp))
+                     .ok_or("raw frame is too large")?;
+    if data.len() < size {
+        return Err("raw frame data is too short".into())
+    }
+    let mut img = RgbImage::new(width, height);
+    for (row, pixels) in img.rows_mut().enumerate() {
+        let offset = row * stride;
+        let src = data[offset..offset + width as usize * bpp].chunks_exact(bpp);
+        for (tgt, src) in pixels.zip(src) {
+            tgt.0 = pixel.to_rgb(src);
         }
-        else { 0 };
-        img = img.crop_imm(x, y, width, height);
-        img_width = img.width();
-        img_height = img.height();
-        debug!("crop -> {}x{}", img_width, img_height);
     }
-  
-    Ok(img.into_rgb8())
+    debug!("raw {}", format);
+    Ok(img)
 }
 
 trait IntoRgb32: Pixel {
@@ -0,0 +1,190 @@
+use core::str::FromStr;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use minifb::Key;
+
+use crate::utils::Result;
+
+/*
+A key bindings file contains lines: KEY = ACTION, e.g.:
+
+# comments start with a hash
+Right = next
+Q = quit
+Escape = none
+
+KEY is a key name as listed in KEYS, case insensitive, ACTION is one of the Action names or "none"
+to remove the default binding.
+*/
+
+/// An action bound to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Next,
+    Prev,
+    First,
+    Last,
+    Up,
+    Down,
+    Open,
+    Grid,
+    Reload,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    Fit,
+    Info,
+    Fullscreen
+}
+
+impl Action {
+    const NAMES: [(&'static str, Action); 16] = [
+        ("quit", Action::Quit),
+        ("next", Action::Next),
+        ("prev", Action::Prev),
+        ("first", Action::First),
+        ("last", Action::Last),
+        ("up", Action::Up),
+        ("down", Action::Down),
+        ("open", Action::Open),
+        ("grid", Action::Grid),
+        ("reload", Action::Reload),
+        ("zoom-in", Action::ZoomIn),
+        ("zoom-out", Action::ZoomOut),
+        ("zoom-reset", Action::ZoomReset),
+        ("fit", Action::Fit),
+        ("info", Action::Info),
+        ("fullscreen", Action::Fullscreen)
+    ];
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        Action::NAMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(s))
+                     .map(|&(_, action)| action)
+                     .ok_or_else(|| format!("unknown key action: {}", s))
+    }
+}
+
+macro_rules! key_names {
+    ($($key:ident),*) => {
+        /// Key names recognized in key bindings files.
+        const KEYS: &[(&str, Key)] = &[$((stringify!($key), Key::$key)),*];
+    };
+}
+
+key_names!(
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, F14, F15,
+    Down, Left, Right, Up, Apostrophe, Backquote, Backslash, Comma, Equal, LeftBracket, Minus, Period,
+    RightBracket, Semicolon, Slash, Backspace, Delete, End, Enter, Escape, Home, Insert, Menu,
+    PageDown, PageUp, Pause, Space, Tab,
+    NumPad0, NumPad1, NumPad2, NumPad3, NumPad4, NumPad5, NumPad6, NumPad7, NumPad8, NumPad9,
+    NumPadDot, NumPadSlash, NumPadAsterisk, NumPadMinus, NumPadPlus, NumPadEnter
+);
+
+fn parse_key(name: &str) -> Option<Key> {
+    KEYS.iter().find(|(key_name, _)| key_name.eq_ignore_ascii_case(name)).map(|&(_, key)| key)
+}
+
+/// Maps an X11 keysym, as sent by VNC clients, to a key.
+pub fn from_keysym(keysym: u32) -> Option<Key> {
+    let name = match keysym {
+        0x30..=0x39 => format!("Key{}", keysym - 0x30),
+        0x41..=0x5a|0x61..=0x7a => char::from(keysym as u8).to_ascii_uppercase().to_string(),
+        0xffb0..=0xffb9 => format!("NumPad{}", keysym - 0xffb0),
+        0xffbe..=0xffcc => format!("F{}", keysym - 0xffbe + 1),
+        _ => return Some(match keysym {
+            0x20 => Key::Space,
+            0x27 => Key::Apostrophe,
+            0x2b|0x3d => Key::Equal,
+            0x2c => Key::Comma,
+            0x2d => Key::Minus,
+            0x2e => Key::Period,
+            0x2f => Key::Slash,
+            0xff08 => Key::Backspace,
+            0xff09 => Key::Tab,
+            0xff0d => Key::Enter,
+            0xff1b => Key::Escape,
+            0xff50 => Key::Home,
+            0xff51 => Key::Left,
+            0xff52 => Key::Up,
+            0xff53 => Key::Right,
+            0xff54 => Key::Down,
+            0xff55 => Key::PageUp,
+            0xff56 => Key::PageDown,
+            0xff57 => Key::End,
+            0xff63 => Key::Insert,
+            0xff8d => Key::NumPadEnter,
+            0xffab => Key::NumPadPlus,
+            0xffad => Key::NumPadMinus,
+            0xffff => Key::Delete,
+            _ => return None
+        })
+    };
+    parse_key(&name)
+}
+
+/// Maps keys to actions.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    map: HashMap<Key, Action>
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Action::*;
+        let map = [
+            (Key::Escape, Quit),
+            (Key::Right, Next), (Key::Space, Next), (Key::PageDown, Next),
+            (Key::Left, Prev), (Key::Backspace, Prev), (Key::PageUp, Prev),
+            (Key::Home, First),
+            (Key::End, Last),
+            (Key::Up, Up), (Key::Down, Down),
+            (Key::Enter, Open), (Key::NumPadEnter, Open),
+            (Key::G, Grid),
+            (Key::R, Reload), (Key::F5, Reload),
+            (Key::Equal, ZoomIn), (Key::NumPadPlus, ZoomIn),
+            (Key::Minus, ZoomOut), (Key::NumPadMinus, ZoomOut),
+            (Key::Key0, ZoomReset), (Key::NumPad0, ZoomReset),
+            (Key::Z, Fit),
+            (Key::I, Info),
+            (Key::F, Fullscreen), (Key::F11, Fullscreen)
+        ].iter().copied().collect();
+        Bindings { map }
+    }
+}
+
+impl Bindings {
+    /// Reads the default bindings overridden with the bindings from the file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut bindings = Bindings::default();
+        let content = fs::read_to_string(path)?;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue
+            }
+            let (key, action) = line.split_once('=').map(|(key, action)| (key.trim(), action.trim()))
+                                    .ok_or_else(|| format!("line {}: expected KEY = ACTION", index + 1))?;
+            let key = parse_key(key).ok_or_else(|| format!("line {}: unknown key: {}", index + 1, key))?;
+            if action.eq_ignore_ascii_case("none") {
+                bindings.map.remove(&key);
+            }
+            else {
+                let action = action.parse().map_err(|err| format!("line {}: {}", index + 1, err))?;
+                bindings.map.insert(key, action);
+            }
+        }
+        Ok(bindings)
+    }
+
+    pub fn get(&self, key: Key) -> Option<Action> {
+        self.map.get(&key).copied()
+    }
+}
@@ -1,18 +1,48 @@
 // #![windows_subsystem = "windows"] // it is "console" by default
 use core::time::Duration;
-use std::sync::mpsc::TryRecvError;
+use std::sync::mpsc::{channel, Sender, TryRecvError};
 use env_logger::Env;
-use log::debug;
-use minifb::{Key, Window, WindowOptions};
+use log::{debug, warn};
+use minifb::{KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
 
 mod opts;
 mod images;
+mod keys;
+mod text;
+mod fetch;
+mod mirror;
+mod mjpeg;
+mod vnc;
 mod remote;
+mod osc;
+mod regions;
+mod grid;
+mod hud;
+mod widgets;
+mod qr;
+mod patterns;
+mod backgrounds;
+mod transfer;
 mod utils;
 
 use utils::{Result, ExitError, err_code};
 use opts::*;
+use keys::Action;
+use remote::Display;
+use transfer::Upload;
+
+/// The file name for reading the image from the standard input.
+const STDIN_NAME: &str = "-";
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / 60);
+/// The zoom factor change per a zoom key press or a mouse wheel step.
+const ZOOM_STEP: f64 = 1.25;
 
 fn run() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("error")).init();
@@ -28,8 +58,8 @@ fn run() -> Result<()> {
             an existing window. A new window appears only if the server doesn't respond\n\
             within the timeout. To disable sending a command at all, set the timeout to 0.")
         .get_matches();
-    let cfg = Config::new(&matches)?;
-    let Config { name, color, width, height, .. } = cfg;
+    let mut cfg = Config::new(&matches)?;
+    let Config { name, color, .. } = cfg;
 
     debug!("{:?}", cfg);
 
@@ -37,14 +67,48 @@ fn run() -> Result<()> {
         utils::free_console_window();
     }
 
+    let local = remote::resolve(cfg.bind, 0)?;
+
+    if cfg.discover {
+        let target = match cfg.group {
+            Some(group) => group,
+            None if cfg.remote != DEFAULT_ADDRESS => remote::resolve(cfg.remote, cfg.port)?[0],
+            None => (Ipv4Addr::BROADCAST, cfg.port).into()
+        };
+        let found = remote::discover(target, remote::local_for(&local, target),
+                                     Duration::from_secs(cfg.timeout))?;
+        print_discovered(&found, cfg.json);
+        return Ok(())
+    }
+
     // check remote
-    let timeout = Duration::from_secs(cfg.timeout);
-    if let Some(res) = remote::send((cfg.remote, cfg.port),
-                                    (cfg.bind, 0),
-                                    timeout, color, name)? {
+    let from_stdin = name == STDIN_NAME;
+    let content = if from_stdin {
+        Some(match cfg.raw {
+            Some(raw) => read_frame(&mut std::io::stdin().lock(), raw.frame_size())?
+                            .ok_or("the standard input is empty")?,
+            None => read_stdin()?
+        })
+    }
+    else if (cfg.push && cfg.timeout != 0 || cfg.raw.is_some()) && !name.is_empty() {
+        Some(std::fs::read(name)?)
+    }
+    else {
+        None
+    };
+    // raw frames read from the standard input are streamed until its end
+    let stream = from_stdin && cfg.raw.is_some();
+    let (packet, uploads) = display_packet(&cfg, content.as_deref())?;
+    if let Some(res) = send_display(&cfg, &local, &packet, &uploads)? {
+        if res && stream {
+            stream_frames(&cfg, &local)?;
+        }
         return if res {
             Ok(())
         }
+        else if cfg.group.is_some() {
+            err_code("some of the remote processes failed to load the image", 2)
+        }
         else {
             err_code("the remote process failed to load the image", 2)
         }
@@ -54,79 +118,926 @@ fn run() -> Result<()> {
         return err_code("the remote process failed to respond in time", 3)
     }
 
+    let bindings = match cfg.keys {
+        "" => keys::Bindings::default(),
+        path => keys::Bindings::from_file(path).map_err(|err| format!("{}: {}", path, err))?
+    };
+    let mut regions = match cfg.regions {
+        "" => regions::Regions::default(),
+        spec => regions::Regions::new(spec, cfg.scale, cfg.align.unwrap_or_default(), cfg.width, cfg.height)?
+    };
+    if !cfg.region.is_empty() && regions.find(cfg.region).is_none() {
+        return Err(format!("unknown region: {}", cfg.region).into())
+    }
+    if !cfg.grid.is_empty() && !Path::new(cfg.grid).is_dir() {
+        return Err(format!("not a directory: {}", cfg.grid).into())
+    }
+    // the caption style of displays not setting their own
+    let text_style = text::TextStyle {
+        font: match cfg.font {
+            "" => None,
+            path => Some(text::load_font(path)?)
+        },
+        size: cfg.font_size,
+        color: cfg.text_color,
+        outline: cfg.outline,
+        outline_color: cfg.outline_color,
+        anchor: cfg.text_anchor
+    };
+    // the background of displays not setting their own
+    let background = cfg.background.map(backgrounds::Background::parse).transpose()?;
+
     if cfg.detach {
-        let pid = daemonize_with(cfg)?;
+        // only the first frame could be piped to the window process without waiting for the end of the stream
+        if stream {
+            return Err("raw frames from the standard input can't be streamed to a detached window".into())
+        }
+        let pid = daemonize_with(cfg, content.as_deref().filter(|_| from_stdin))?;
         println!("{}", pid);
         return Ok(())
     }
 
+    // the window geometry restored when leaving the fullscreen mode
+    let mut windowed = Config { fullscreen: false, ..cfg };
+    if cfg.fullscreen {
+        let (width, height) = utils::screen_size().ok_or("the screen size could not be determined")?;
+        debug!("screen size: {}x{}", width, height);
+        cfg.width = width;
+        cfg.height = height;
+        cfg.xwin = 0;
+        cfg.ywin = 0;
+    }
+    let (mut width, mut height) = (cfg.width, cfg.height);
+    regions.resize(width, height);
+    // the window layout, images may be aligned differently
+    let base = images::Layout {
+        align: cfg.align.unwrap_or_default(),
+        offset: cfg.offset.unwrap_or_default(),
+        ..images::Layout::new(cfg.scale)
+    };
+    let mut layout = base;
+    // whether the image information is drawn over the image
+    let mut overlay = cfg.hud;
+    // the last mouse position while dragging the image
+    let mut drag: Option<(f32, f32)> = None;
+    // the clock, the countdown and the elapsed timer drawn over the window contents
+    let mut widgets = widgets::Widgets::new(cfg.clock, cfg.timezone, cfg.countdown, cfg.elapsed, text::TextStyle {
+        anchor: Some(cfg.widget_anchor.unwrap_or(images::Align::TopRight)),
+        ..text_style.clone()
+    });
+    if let Some(widgets) = widgets.as_mut() {
+        widgets.update();
+    }
+    // the contact sheet, kept when an image is opened from it to get back to it
+    let mut sheet: Option<grid::Sheet> = None;
+    // whether the contact sheet is shown and whether it needs to be rendered again
+    let mut sheet_active = false;
+    let mut sheet_dirty = false;
+
     // allocate buffer
     let mut buffer: Vec<u32> = vec![color; width * height];
+    for region in regions.list.iter() {
+        region.draw_into(None, &mut buffer, width, height);
+    }
+
+    let loader = images::Loader {
+        with_info: cfg.info,
+        fetch: fetch::Fetch {
+            timeout: Duration::from_secs(cfg.http_timeout),
+            max_size: cfg.max_size << 20,
+            cache_dir: (!cfg.no_cache).then(|| match cfg.cache_dir {
+                "" => std::env::temp_dir().join("riv-cache"),
+                dir => dir.into()
+            })
+        }
+    };
+
+    // bind sockets
+    let (main_send, recv) = channel();
+    if let Some(osc_port) = cfg.osc_port {
+        osc::bind(remote::resolve(cfg.bind, osc_port)?[0], cfg.osc_prefix, color,
+                  loader.clone(), main_send.clone())?;
+    }
+    let instance = remote::Instance {
+        id: cfg.id.to_string(),
+        group: cfg.group,
+        xwin: cfg.xwin,
+        ywin: cfg.ywin,
+        color,
+        name: name.to_string(),
+        size: Arc::new(Mutex::new((width, height))),
+        info: Arc::new(Mutex::new(String::new())),
+        regions: regions.names()
+    };
+    let window_size = instance.size.clone();
+    let shown_info = instance.info.clone();
+    remote::bind(remote::resolve(cfg.bind, cfg.port)?[0], instance, loader.clone(), main_send.clone())?;
+
+    // a display waiting for its presentation time with its pre-rendered buffer
+    let mut pending: Option<(SystemTime, Vec<u32>, Display)> = None;
+    // the display currently shown, to recompose the buffer when the window is resized
+    let mut shown: Option<Display> = None;
+
+    if !cfg.grid.is_empty() {
+        sheet = Some(grid::Sheet::new(cfg.grid, color, cfg.auto_color, cfg.captions)?);
+        sheet_active = true;
+        sheet_dirty = true;
+    }
+    // load image if file or render the QR code, or just draw the test pattern or the caption
+    else if !name.is_empty() || cfg.text.is_some() || cfg.qr.is_some() || cfg.pattern.is_some() {
+        let img = match (content.as_ref().filter(|_| from_stdin || cfg.raw.is_some()), cfg.raw) {
+            _ if cfg.qr.is_some() => cfg.qr.map(|text| qr::render(text, &cfg.qr_style)).transpose()?,
+            _ if name.is_empty() => None,
+            (Some(data), Some(raw)) => Some(loader.load_raw(data, &raw)?),
+            (Some(data), None) => Some(loader.load_from_memory(data)?),
+            (None, _) => Some(loader.load(name)?)
+        };
+        let (img, meta) = img.unzip();
+        let display = Display {
+            present_at: cfg.present_at,
+            name: if from_stdin { String::new() } else { name.to_string() },
+            region: (!cfg.region.is_empty()).then(|| cfg.region.to_string()),
+            zoom: cfg.zoom,
+            center: cfg.pan,
+            scale: cfg.qr.map(|_| images::ScaleMode::Integer),
+            pattern: cfg.pattern,
+            auto_color: cfg.auto_color,
+            meta,
+            ..Display::new(color, img)
+        };
+        let display = display_defaults(display, cfg.text, &text_style, background.as_ref());
+        update_layout(&mut layout, &base, &display, None, (width, height));
+        if let Some(at) = cfg.present_at {
+            let mut back = if display.region.is_some() { buffer.clone() } else { vec![color; width * height] };
+            draw_display(&display, &layout, overlay, &regions, width, height, &mut back);
+            pending = Some((at, back, display));
+        }
+        else {
+            draw_display(&display, &layout, overlay, &regions, width, height, &mut buffer);
+            set_shown(display, &mut shown, &mut regions);
+        }
+    }
 
-    // bind socket
-    let recv = remote::bind((cfg.bind, cfg.port), width as u32, height as u32, cfg.info)?;
+    // the copy of the window contents for streaming services
+    let mirror = (cfg.mjpeg_port.is_some() || cfg.vnc_port.is_some())
+                 .then(|| mirror::Mirror::new(width, height, color));
+    if let (Some(mjpeg_port), Some(mirror)) = (cfg.mjpeg_port, mirror.as_ref()) {
+        mjpeg::bind(remote::resolve(cfg.bind, mjpeg_port)?[0], mirror.clone(),
+                    cfg.mjpeg_quality, cfg.mjpeg_scale)?;
+    }
+    let (key_send, key_recv) = channel();
+    if let (Some(vnc_port), Some(mirror)) = (cfg.vnc_port, mirror.as_ref()) {
+        let title = if cfg.id.is_empty() { "Royal Image Viewer" } else { cfg.id };
+        vnc::bind(remote::resolve(cfg.bind, vnc_port)?[0], title.to_string(), mirror.clone(),
+                  Some(key_send).filter(|_| cfg.vnc_input))?;
+    }
 
-    // load image if file
-    if !name.is_empty() {
-        images::load_image_center_into(
-            name, color, width as u32, height as u32, buffer.as_mut(), cfg.info)?;
+    // show the following raw frames
+    if let Some(raw) = cfg.raw.filter(|_| stream) {
+        let loader = loader.clone();
+        let main_send = main_send.clone();
+        let auto_color = cfg.auto_color;
+        thread::spawn(move || {
+            let mut stdin = std::io::stdin().lock();
+            loop {
+                let (img, meta) = match read_frame(&mut stdin, raw.frame_size()).map(|frame| {
+                    frame.map(|frame| loader.load_raw(&frame, &raw)).transpose()
+                }) {
+                    Ok(Ok(Some(loaded))) => loaded,
+                    Ok(Ok(None)) => break,
+                    Ok(Err(err))|Err(err) => {
+                        warn!("reading raw frame failed: {}", err);
+                        break
+                    }
+                };
+                if main_send.send(Display { auto_color, meta: Some(meta), ..Display::new(color, Some(img)) }).is_err() {
+                    break
+                }
+            }
+            debug!("end of raw frames");
+        });
     }
 
     // open window
+    let mut window = if cfg.headless { None } else { Some(open_window(&cfg, width, height)?) };
+    // Draw a buffer with preloaded image
+    show(window.as_mut(), &buffer, width, height, mirror.as_ref(), widgets.as_ref())?;
+    publish_info(&shown_info, shown.as_ref(), &layout, (width, height));
+
+    loop {
+        if window.as_ref().is_some_and(|window| !window.is_open()) {
+            break
+        }
+        // keys pressed in the window and in vnc clients
+        let actions: Vec<Action> = window.as_ref().map(|window| window.get_keys_pressed(KeyRepeat::Yes))
+                                         .unwrap_or_default().into_iter()
+                                         .chain(key_recv.try_iter().filter(|key| key.down)
+                                                        .filter_map(|key| keys::from_keysym(key.keysym)))
+                                         .filter_map(|key| bindings.get(key))
+                                         .collect();
+        if !cfg.nkey && actions.contains(&Action::Quit) {
+            break
+        }
+        let mut relayout = false;
+        for action in actions {
+            debug!("key action: {:?}", action);
+            if let Some(sheet) = sheet.as_mut().filter(|_| sheet_active) {
+                match action {
+                    _ if sheet.navigate(action) => sheet_dirty = true,
+                    Action::Open => {
+                        if let Some(file) = sheet.selected_file() {
+                            load_in_background(&loader, file.to_string_lossy().into_owned(), sheet.color,
+                                               sheet.auto_color, main_send.clone());
+                        }
+                    }
+                    Action::Grid => {
+                        sheet_active = false;
+                        relayout = true;
+                    }
+                    _ => {}
+                }
+                if action != Action::Fullscreen {
+                    continue
+                }
+            }
+            match action {
+                Action::Quit|Action::Up|Action::Down|Action::Open => {}
+                Action::Grid => {
+                    let shown_name = shown.as_ref().map_or("", |display| display.name.as_str());
+                    match sheet_dir(shown_name) {
+                        Some(dir) => {
+                            if sheet.as_ref().is_none_or(|sheet| sheet.dir != dir) {
+                                let (color, auto_color) = shown.as_ref().map_or((color, cfg.auto_color), |display| {
+                                    (display.color, display.auto_color)
+                                });
+                                match grid::Sheet::new(&dir, color, auto_color, cfg.captions) {
+                                    Ok(new_sheet) => sheet = Some(new_sheet),
+                                    Err(err) => {
+                                        warn!("listing images failed: {}", err);
+                                        continue
+                                    }
+                                }
+                            }
+                            if let Some(sheet) = sheet.as_mut() {
+                                sheet.select_file(shown_name);
+                            }
+                            sheet_active = true;
+                            sheet_dirty = true;
+                        }
+                        None => continue
+                    }
+                }
+                Action::Next|Action::Prev|Action::First|Action::Last|Action::Reload => {
+                    if let Some(display) = shown.as_ref() {
+                        match target_name(&display.name, action) {
+                            Ok(Some(target)) => {
+                                load_in_background(&loader, target, display.color, display.auto_color, main_send.clone())
+                            }
+                            Ok(None) => {}
+                            Err(err) => warn!("listing images failed: {}", err)
+                        }
+                    }
+                }
+                Action::ZoomIn => layout.set_zoom(layout.zoom * ZOOM_STEP),
+                Action::ZoomOut => layout.set_zoom(layout.zoom / ZOOM_STEP),
+                Action::ZoomReset => layout = images::Layout { align: layout.align, offset: layout.offset, ..base },
+                Action::Fit => layout = images::Layout {
+                    scale: images::ScaleMode::Fit,
+                    align: layout.align,
+                    offset: layout.offset,
+                    ..base
+                },
+                Action::Info => overlay = !overlay,
+                Action::Fullscreen if window.is_some() => {
+                    if cfg.fullscreen {
+                        cfg = windowed;
+                    }
+                    else if let Some((screen_width, screen_height)) = utils::screen_size() {
+                        windowed = Config { width, height, ..cfg };
+                        cfg = Config {
+                            width: screen_width,
+                            height: screen_height,
+                            xwin: 0,
+                            ywin: 0,
+                            fullscreen: true,
+                            borders: false,
+                            resizable: false,
+                            ..cfg
+                        };
+                    }
+                    else {
+                        warn!("the screen size could not be determined");
+                        continue
+                    }
+                    width = cfg.width;
+                    height = cfg.height;
+                    *window_size.lock().unwrap() = (width, height);
+                    drop(window.take());
+                    window = Some(open_window(&cfg, width, height)?);
+                }
+                Action::Fullscreen => {}
+            }
+            relayout = true;
+        }
+        // drag to pan and wheel to zoom around the mouse cursor
+        if let (Some(window), Some(img), false) = (window.as_ref(),
+                                                   shown.as_ref().and_then(|display| display.image.as_ref()),
+                                                   sheet_active)
+        {
+            let buf_size = (width as u32, height as u32);
+            let mouse = window.get_mouse_pos(MouseMode::Discard);
+            if window.get_mouse_down(MouseButton::Left) {
+                if let Some(((x0, y0), (x, y))) = drag.zip(mouse).filter(|(drag, mouse)| drag != mouse) {
+                    layout.pan_by(((x - x0) as f64, (y - y0) as f64), img.dimensions(), buf_size);
+                    relayout = true;
+                }
+                drag = mouse;
+            }
+            else {
+                drag = None;
+            }
+            if let (Some((_, wheel)), Some((x, y))) = (window.get_scroll_wheel(), mouse) {
+                if wheel != 0.0 {
+                    let zoom = if wheel > 0.0 { layout.zoom * ZOOM_STEP } else { layout.zoom / ZOOM_STEP };
+                    layout.zoom_at(zoom, (x as f64, y as f64), img.dimensions(), buf_size);
+                    relayout = true;
+                }
+            }
+        }
+        if let Some((new_width, new_height)) = window.as_ref().filter(|_| cfg.resizable).map(Window::get_size)
+                                                    .filter(|&(w, h)| (w, h) != (width, height) && w * h != 0)
+        {
+            debug!("resized to {}x{}", new_width, new_height);
+            width = new_width;
+            height = new_height;
+            *window_size.lock().unwrap() = (width, height);
+            relayout = true;
+        }
+        if relayout {
+            buffer = recompose((width, height), &layout, overlay, color, shown.as_ref(), pending.as_mut(), &mut regions);
+            publish_info(&shown_info, shown.as_ref(), &layout, (width, height));
+            sheet_dirty = true;
+            if !sheet_active {
+                show(window.as_mut(), &buffer, width, height, mirror.as_ref(), widgets.as_ref())?;
+            }
+        }
+        if let Some(sheet) = sheet.as_mut().filter(|_| sheet_active) {
+            // render again as the thumbnails get decoded
+            if sheet.poll() || sheet_dirty {
+                buffer = sheet.render(width, height);
+                show(window.as_mut(), &buffer, width, height, mirror.as_ref(), widgets.as_ref())?;
+            }
+            sheet_dirty = false;
+        }
+        if let Some((at, ..)) = pending.as_ref() {
+            let now = SystemTime::now();
+            let remaining = at.duration_since(now).unwrap_or_default();
+            if remaining < FRAME_DURATION {
+                thread::sleep(remaining);
+                debug!("presenting prepared display");
+                let (_, back, display) = pending.take().unwrap();
+                buffer = back;
+                sheet_active &= display.region.is_some();
+                set_shown(display, &mut shown, &mut regions);
+                show(window.as_mut(), &buffer, width, height, mirror.as_ref(), widgets.as_ref())?;
+                publish_info(&shown_info, shown.as_ref(), &layout, (width, height));
+                continue;
+            }
+        }
+        // the widgets change at most once per second
+        if widgets.as_mut().is_some_and(widgets::Widgets::update) {
+            show(window.as_mut(), &buffer, width, height, mirror.as_ref(), widgets.as_ref())?;
+        }
+        match recv.try_recv() {
+            Ok(display) => {
+                // draw all queued displays, but update the window only once per frame
+                let mut next = Some(display);
+                let mut updated = false;
+                while let Some(display) = next.take() {
+                    let display = display_defaults(display, cfg.text, &text_style, background.as_ref());
+                    if let Some((new_width, new_height)) = display.resize.filter(|&size| size != (width, height)) {
+                        debug!("resizing to {}x{}", new_width, new_height);
+                        width = new_width;
+                        height = new_height;
+                        *window_size.lock().unwrap() = (width, height);
+                        // minifb windows can't be resized, so the window is opened again
+                        if window.take().is_some() {
+                            window = Some(open_window(&cfg, width, height)?);
+                        }
+                        buffer = recompose((width, height), &layout, overlay, color, shown.as_ref(), pending.as_mut(), &mut regions);
+                        sheet_dirty = true;
+                        updated = true;
+                    }
+                    update_layout(&mut layout, &base, &display, shown.as_ref(), (width, height));
+                    if let Some(captions) = display.grid {
+                        match grid::Sheet::new(&display.name, display.color, display.auto_color, captions) {
+                            Ok(new_sheet) => {
+                                sheet = Some(new_sheet);
+                                sheet_active = true;
+                                sheet_dirty = true;
+                            }
+                            Err(err) => warn!("listing images failed: {}", err)
+                        }
+                    }
+                    else if let Some(region) = display.region.as_ref().filter(|region| regions.find(region).is_none()) {
+                        warn!("unknown region: {}", region);
+                    }
+                    else if let Some(at) = display.present_at {
+                        debug!("preparing display for {:?}", at);
+                        // a region is drawn over the current window contents
+                        let mut back = match pending.take() {
+                            _ if display.region.is_some() => buffer.clone(),
+                            Some((_, back, _)) => back,
+                            None => vec![display.color; width * height]
+                        };
+                        draw_display(&display, &layout, overlay, &regions, width, height, &mut back);
+                        pending = Some((at, back, display));
+                    }
+                    else {
+                        pending = None;
+                        sheet_active &= display.region.is_some();
+                        draw_display(&display, &layout, overlay, &regions, width, height, &mut buffer);
+                        set_shown(display, &mut shown, &mut regions);
+                        updated = true;
+                    }
+                    next = recv.try_recv().ok();
+                }
+                if updated {
+                    show(window.as_mut(), &buffer, width, height, mirror.as_ref(), widgets.as_ref())?;
+                    publish_info(&shown_info, shown.as_ref(), &layout, (width, height));
+                }
+                else {
+                    idle(window.as_mut());
+                }
+            }
+            Err(TryRecvError::Empty) => idle(window.as_mut()),
+            Err(TryRecvError::Disconnected) => break
+        }
+    }
+
+    Ok(())
+}
+
+fn print_discovered(found: &[remote::Discovered], json: bool) {
+    if json {
+        println!("[");
+        for (index, inst) in found.iter().enumerate() {
+            println!("  {{\"address\": {}, \"id\": {}, \"width\": {}, \"height\": {}, \
+                      \"x\": {}, \"y\": {}, \"port\": {}, \"color\": \"#{:06x}\", \"image\": {}, \"info\": {}}}{}",
+                utils::json_string(&utils::canonical_addr(inst.addr).ip().to_string()),
+                utils::json_string(&inst.id),
+                inst.width, inst.height, inst.xwin, inst.ywin, inst.port, inst.color,
+                utils::json_string(&inst.name),
+                if inst.info.is_empty() { "null" } else { &inst.info },
+                if index + 1 < found.len() { "," } else { "" });
+        }
+        println!("]");
+    }
+    else {
+        println!("{:<24} {:<16} {:<24} {:>5} {:<7} IMAGE", "ADDRESS", "ID", "GEOMETRY", "PORT", "COLOR");
+        for inst in found.iter() {
+            println!("{:<24} {:<16} {:<24} {:>5} #{:06x} {}",
+                utils::canonical_addr(inst.addr).ip(), inst.id,
+                format!("{}x{}{:+}{:+}", inst.width, inst.height, inst.xwin, inst.ywin),
+                inst.port, inst.color, inst.name);
+        }
+    }
+}
+
+/// Creates the display command packet with uploads of the image `content` or a long name.
+fn display_packet<'a>(cfg: &Config<'a>, content: Option<&'a [u8]>) -> Result<(remote::RivPacket, Vec<Upload<'a>>)> {
+    let name = match (cfg.qr, cfg.grid) {
+        (Some(text), _) => text,
+        (None, "") => cfg.name,
+        (None, dir) => dir
+    };
+    let long_name = name.len() > remote::MAX_NAME_LENGTH;
+    let mut packet = remote::RivPacket::new(cfg.color, if long_name { "" } else { name })?;
+    let mut uploads = Vec::new();
+    if let Some(content) = content {
+        let upload = Upload::new(content)?;
+        packet.push_param("data", &upload.id.to_string())?;
+        if let Some(raw) = cfg.raw {
+            packet.push_param("raw", &raw.to_string())?;
+        }
+        uploads.push(upload);
+    }
+    else if long_name {
+        let upload = Upload::new(name.as_bytes())?;
+        packet.push_param("longname", &upload.id.to_string())?;
+        uploads.push(upload);
+    }
+    if cfg.resize {
+        packet.push_param("size", &format!("{}x{}", cfg.width, cfg.height))?;
+    }
+    if cfg.auto_color {
+        packet.push_param("color", "auto")?;
+    }
+    if !cfg.grid.is_empty() {
+        packet.push_param("grid", if cfg.captions { "captions" } else { "plain" })?;
+    }
+    if cfg.qr.is_some() {
+        packet.push_param("qr", qr::level_name(cfg.qr_style.level))?;
+        packet.push_param("qr-margin", &cfg.qr_style.margin.to_string())?;
+        packet.push_param("qr-color", &format!("#{:06x}", cfg.qr_style.color))?;
+        packet.push_param("qr-background", &format!("#{:06x}", cfg.qr_style.background))?;
+    }
+    if let Some(pattern) = cfg.pattern {
+        packet.push_param("pattern", &pattern.to_string())?;
+    }
+    if let Some(background) = cfg.background {
+        packet.push_param("background", background)?;
+    }
+    if !cfg.region.is_empty() {
+        packet.push_param("region", cfg.region)?;
+    }
+    if let Some(align) = cfg.align {
+        packet.push_param("align", align.name())?;
+    }
+    if let Some((x, y)) = cfg.offset {
+        packet.push_param("offset", &format!("{},{}", x, y))?;
+    }
+    if let Some(zoom) = cfg.zoom {
+        packet.push_param("zoom", &zoom.to_string())?;
+    }
+    if let Some((x, y)) = cfg.pan {
+        packet.push_param("pan", &format!("{},{}", x, y))?;
+    }
+    if let Some(text) = cfg.text {
+        packet.push_param("text", &text.replace('\n', "\\n"))?;
+    }
+    if !cfg.font.is_empty() {
+        packet.push_param("font", cfg.font)?;
+    }
+    if let Some(size) = cfg.font_size {
+        packet.push_param("font-size", &size.to_string())?;
+    }
+    if let Some(color) = cfg.text_color {
+        packet.push_param("text-color", &format!("#{:06x}", color))?;
+    }
+    if let Some(width) = cfg.outline {
+        packet.push_param("outline", &width.to_string())?;
+    }
+    if let Some(color) = cfg.outline_color {
+        packet.push_param("outline-color", &format!("#{:06x}", color))?;
+    }
+    if let Some(anchor) = cfg.text_anchor {
+        packet.push_param("text-anchor", anchor.name())?;
+    }
+    if let Some(at) = cfg.present_at {
+        packet.push_param("at", &utils::epoch_millis(at).to_string())?;
+    }
+    Ok((packet, uploads))
+}
+
+/// Sends the display command to the remote process or the group and returns whether it succeeded,
+/// or `None` if nobody responded.
+fn send_display(
+        cfg: &Config,
+        local: &[SocketAddr],
+        packet: &remote::RivPacket,
+        uploads: &[Upload]
+    ) -> Result<Option<bool>>
+{
+    let timeout = Duration::from_secs(cfg.timeout);
+    if let Some(group) = cfg.group {
+        let responses = remote::send_group(group, remote::local_for(local, group), timeout, packet, uploads)?;
+        for resp in responses.iter() {
+            println!("{} {}: {}", utils::canonical_addr(resp.addr), resp.id,
+                     if resp.ok { "ok" } else { "failed" });
+        }
+        Ok((!responses.is_empty()).then(|| responses.iter().all(|resp| resp.ok)))
+    }
+    else {
+        let remote = remote::resolve(cfg.remote, cfg.port)?[0];
+        remote::send(remote, remote::local_for(local, remote), timeout, packet, uploads)
+    }
+}
+
+/// Sends the following raw frames from the standard input to the remote process.
+fn stream_frames(cfg: &Config, local: &[SocketAddr]) -> Result<()> {
+    let raw = cfg.raw.unwrap();
+    let mut stdin = std::io::stdin().lock();
+    while let Some(frame) = read_frame(&mut stdin, raw.frame_size())? {
+        let (packet, uploads) = display_packet(cfg, Some(&frame))?;
+        match send_display(cfg, local, &packet, &uploads)? {
+            Some(true) => {}
+            Some(false) => return err_code("the remote process failed to show the frame", 2),
+            None => return err_code("the remote process stopped responding", 3)
+        }
+    }
+    Ok(())
+}
+
+fn open_window(cfg: &Config, width: usize, height: usize) -> Result<Window> {
     utils::set_dpi_awareness()?;
 
-    let mut winopts = WindowOptions::default();
-    winopts.none = true;
+    let winopts = WindowOptions {
+        none: !cfg.borders,
+        resize: cfg.resizable,
+        topmost: cfg.topmost,
+        ..WindowOptions::default()
+    };
 
     let mut window = Window::new(
-        "Royal Image Viewer",
+        if cfg.title.is_empty() { "Royal Image Viewer" } else { cfg.title },
         width,
         height,
         winopts,
     )?;
 
     window.set_position(cfg.xwin, cfg.ywin);
-    window.set_cursor_visibility(false);
+    // the cursor is only shown in decorated windows
+    window.set_cursor_visibility(cfg.borders);
     // Limit to max ~60 fps update rate
-    window.limit_update_rate(Some(std::time::Duration::from_micros(1_000_000 / 60)));
-    // Draw a buffer with preloaded image
-    window.update_with_buffer(&buffer, width, height)?;
+    window.limit_update_rate(Some(FRAME_DURATION));
+    Ok(window)
+}
 
-    while window.is_open() && (cfg.nkey || !window.is_key_down(Key::Escape)) {
-        match recv.try_recv() {
-            Ok((color, maybeimg)) => {
-                if let Some(img) = maybeimg {
-                    debug!("drawing image with: #{:06x}", color);
-                    images::center_image_into(&img, color, width as u32, height as u32, &mut buffer);
-                }
-                else {
-                    debug!("drawing color: #{:06x}", color);
-                    for p in buffer.iter_mut() {
-                        *p = color;
-                    }
-                }
-                window.update_with_buffer(&buffer, width, height)?;
+/// Updates the window with the `buffer` and the widgets drawn over it and publishes it to streaming services.
+fn show(
+        window: Option<&mut Window>,
+        buffer: &[u32],
+        width: usize,
+        height: usize,
+        mirror: Option<&mirror::Mirror>,
+        widgets: Option<&widgets::Widgets>
+    ) -> Result<()>
+{
+    let frame = widgets.map(|widgets| {
+        let mut frame = buffer.to_vec();
+        widgets.draw(&mut frame, width, height);
+        frame
+    });
+    let buffer = frame.as_deref().unwrap_or(buffer);
+    if let Some(window) = window {
+        window.update_with_buffer(buffer, width, height)?;
+    }
+    if let Some(mirror) = mirror {
+        mirror.publish(buffer, width, height);
+    }
+    Ok(())
+}
+
+/// Processes window events, or just waits for the next frame when headless.
+fn idle(window: Option<&mut Window>) {
+    match window {
+        Some(window) => window.update(),
+        None => thread::sleep(FRAME_DURATION)
+    }
+}
+
+/// Allocates the buffer for the new window size and draws the shown and the pending displays again.
+fn recompose(
+        (width, height): (usize, usize),
+        layout: &images::Layout,
+        overlay: bool,
+        color: u32,
+        shown: Option<&Display>,
+        pending: Option<&mut (SystemTime, Vec<u32>, Display)>,
+        regions: &mut regions::Regions
+    ) -> Vec<u32>
+{
+    regions.resize(width, height);
+    let mut buffer = vec![shown.map_or(color, |display| display.color); width * height];
+    if let Some(display) = shown {
+        draw(display, layout, overlay, width, height, &mut buffer);
+    }
+    for region in regions.list.iter() {
+        region.draw_into(region.display.as_ref(), &mut buffer, width, height);
+    }
+    if let Some((_, back, display)) = pending {
+        *back = if display.region.is_some() { buffer.clone() } else { vec![display.color; width * height] };
+        draw_display(display, layout, overlay, regions, width, height, back);
+    }
+    buffer
+}
+
+/// Draws the display in its region or in the whole window, in which case the regions are cleared.
+fn draw_display(
+        display: &Display,
+        layout: &images::Layout,
+        overlay: bool,
+        regions: &regions::Regions,
+        width: usize,
+        height: usize,
+        buffer: &mut [u32]
+    )
+{
+    match display.region.as_deref().and_then(|region| regions.find(region)) {
+        Some(index) => {
+            regions.list[index].draw_into(Some(display), buffer, width, height);
+            // the following regions may overlap it
+            for region in regions.list[index + 1..].iter() {
+                region.draw_into(region.display.as_ref(), buffer, width, height);
+            }
+        }
+        None => {
+            draw(display, layout, overlay, width, height, buffer);
+            for region in regions.list.iter() {
+                region.draw_into(None, buffer, width, height);
             }
-            Err(TryRecvError::Empty) => window.update(),
-            Err(TryRecvError::Disconnected) => break
         }
     }
+}
 
-    Ok(())
+/// Keeps the drawn display as the one shown in its region or in the whole window.
+fn set_shown(display: Display, shown: &mut Option<Display>, regions: &mut regions::Regions) {
+    match display.region.as_deref().and_then(|region| regions.find(region)) {
+        Some(index) => regions.list[index].display = Some(display),
+        None => {
+            regions.clear();
+            *shown = Some(display);
+        }
+    }
 }
 
-fn daemonize_with(mut cfg: Config) -> Result<u32> {
+fn draw(
+        display: &Display,
+        layout: &images::Layout,
+        overlay: bool,
+        width: usize,
+        height: usize,
+        buffer: &mut [u32]
+    )
+{
+    let color = display.color;
+    if let Some(pattern) = display.pattern {
+        debug!("drawing pattern: {}", pattern);
+        pattern.draw(buffer, width, height);
+    }
+    else {
+        if let Some(background) = display.background.as_ref() {
+            background.draw(display.image.as_ref(), color, buffer, width, height);
+        }
+        if let Some(img) = display.image.as_ref() {
+            debug!("drawing image with: #{:06x}", color);
+            let bgpixel = display.background.is_none().then_some(color);
+            images::compose_into(img, layout, bgpixel, width as u32, height as u32, buffer);
+            if overlay {
+                hud::draw(&hud::Info::new(display, img, layout, (width, height)).lines(), width, height, buffer);
+            }
+        }
+        else if display.background.is_none() {
+            debug!("drawing color: #{:06x}", color);
+            for p in buffer.iter_mut() {
+                *p = color;
+            }
+        }
+    }
+    if let Some(caption) = display.caption() {
+        text::Canvas { buf: buffer, width, height }.draw_caption(&caption, &display.text_style);
+    }
+}
+
+/// Sets the caption text and style and the background not given by the display to the window defaults,
+/// and the color computed from the image edges if requested.
+fn display_defaults(
+        mut display: Display,
+        text: Option<&str>,
+        style: &text::TextStyle,
+        background: Option<&backgrounds::Background>
+    ) -> Display
+{
+    display.text = display.text.or_else(|| text.map(str::to_string));
+    display.text_style = display.text_style.or(style);
+    display.background = display.background.or_else(|| background.cloned());
+    if let Some(meta) = display.meta.as_ref().filter(|_| display.auto_color) {
+        display.color = meta.edge_color;
+    }
+    display
+}
+
+/// Sets the alignment, the zoom and the pan requested by the whole window display, the alignment falls back
+/// to the `base` one, the zoom and the pan are reset for images of a different size than the `shown` one,
+/// but kept e.g. for reloaded or streamed images.
+fn update_layout(
+        layout: &mut images::Layout,
+        base: &images::Layout,
+        display: &Display,
+        shown: Option<&Display>,
+        (width, height): (usize, usize)
+    )
+{
+    let img = match display.image.as_ref().filter(|_| display.region.is_none()) {
+        Some(img) => img,
+        None => return
+    };
+    if shown.and_then(|shown| shown.image.as_ref()).map(|shown| shown.dimensions()) != Some(img.dimensions()) {
+        *layout = images::Layout { scale: layout.scale, ..*base };
+    }
+    if let Some(scale) = display.scale {
+        layout.scale = scale;
+    }
+    else if shown.is_some_and(|shown| shown.scale.is_some()) {
+        layout.scale = base.scale;
+    }
+    layout.align = display.align.unwrap_or(base.align);
+    layout.offset = display.offset.unwrap_or(base.offset);
+    if let Some(zoom) = display.zoom {
+        layout.set_zoom(zoom);
+    }
+    if let Some(center) = display.center {
+        layout.set_center(center, img.dimensions(), (width as u32, height as u32));
+    }
+}
+
+/// Updates the JSON information about the image shown in the whole window reported on discovery.
+fn publish_info(info: &Mutex<String>, shown: Option<&Display>, layout: &images::Layout, size: (usize, usize)) {
+    *info.lock().unwrap() = match shown.and_then(|display| display.image.as_ref().map(|img| (display, img))) {
+        Some((display, img)) => hud::Info::new(display, img, layout, size).to_json(),
+        None => String::new()
+    };
+}
+
+/// Returns the name of the image the navigation `action` leads to from the image `name`,
+/// only local files can be navigated.
+fn target_name(name: &str, action: Action) -> Result<Option<String>> {
+    if name.is_empty() || action == Action::Reload {
+        return Ok(Some(name.to_string()).filter(|name| !name.is_empty()))
+    }
+    if fetch::is_http_url(name) {
+        return Ok(None)
+    }
+    let path = fetch::file_url_path(name).unwrap_or_else(|| name.into());
+    let files = images::sibling_images(&path)?;
+    let current = files.iter().position(|file| file.file_name() == path.file_name());
+    let last = match files.len().checked_sub(1) {
+        Some(last) => last,
+        None => return Ok(None)
+    };
+    let index = match (action, current) {
+        (Action::First, _) => 0,
+        (Action::Last, _) => last,
+        (Action::Next, Some(current)) if current < last => current + 1,
+        (Action::Next, _) => 0,
+        (Action::Prev, Some(current)) if current > 0 => current - 1,
+        (Action::Prev, _) => last,
+        _ => return Ok(None)
+    };
+    Ok(Some(files[index].to_string_lossy().into_owned()).filter(|target| target != name))
+}
+
+/// Returns the directory of the local image file for its contact sheet.
+fn sheet_dir(name: &str) -> Option<String> {
+    if name.is_empty() || fetch::is_http_url(name) {
+        return None
+    }
+    let path = fetch::file_url_path(name).unwrap_or_else(|| name.into());
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    Some(dir.to_string_lossy().into_owned())
+}
+
+/// Loads the image in a separate thread and sends it to the main loop to show it.
+fn load_in_background(loader: &images::Loader, name: String, color: u32, auto_color: bool, send: Sender<Display>) {
+    let loader = loader.clone();
+    thread::spawn(move || match loader.load(&name) {
+        Ok((img, meta)) => {
+            let _ = send.send(Display { name, auto_color, meta: Some(meta), ..Display::new(color, Some(img)) });
+        }
+        Err(err) => warn!("loading {} failed: {}", name, err)
+    });
+}
+
+/// Reads the image file content from the standard input.
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::io::stdin().lock().take(transfer::MAX_TRANSFER_SIZE as u64 + 1).read_to_end(&mut data)?;
+    if data.is_empty() {
+        return Err("the standard input is empty".into())
+    }
+    if data.len() > transfer::MAX_TRANSFER_SIZE {
+        return Err("the standard input data is too large".into())
+    }
+    Ok(data)
+}
+
+/// Reads a single raw frame, returns `None` at the end of the input.
+fn read_frame<R: Read>(input: &mut R, size: usize) -> Result<Option<Vec<u8>>> {
+    let mut frame = Vec::with_capacity(size);
+    input.take(size as u64).read_to_end(&mut frame)?;
+    match frame.len() {
+        0 => Ok(None),
+        len if len == size => Ok(Some(frame)),
+        _ => Err("the raw frame on the standard input is incomplete".into())
+    }
+}
+
+/// Spawns the window process, the `stdin_data` is piped to its standard input.
+fn daemonize_with(mut cfg: Config, stdin_data: Option<&[u8]>) -> Result<u32> {
     cfg.timeout = 0;
     cfg.detach = false;
     cfg.mswin_free_console = true;
-    Command::new(std::env::args().next().unwrap())
+    let mut child = Command::new(std::env::args().next().unwrap())
        .args_from(&cfg)
-       .stdin(Stdio::null())
+       .stdin(if stdin_data.is_some() { Stdio::piped() } else { Stdio::null() })
        .stdout(Stdio::inherit())
        .stderr(Stdio::inherit())
-       .spawn()
-       .map_err(|e| e.into())
-       .map(|child| child.id())
+       .spawn()?;
+    if let (Some(data), Some(mut stdin)) = (stdin_data, child.stdin.take()) {
+        stdin.write_all(data)?;
+    }
+    Ok(child.id())
 }
 
 fn main() -> Result<()> {
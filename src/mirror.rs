@@ -0,0 +1,52 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// A copy of the window buffer shared with the services streaming the window contents.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    shared: Arc<(Mutex<Frame>, Condvar)>
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    /// Incremented each time the buffer is published.
+    pub generation: u64,
+    pub width: usize,
+    pub height: usize,
+    /// Pixels in the 0RGB format, as in the window buffer.
+    pub buffer: Vec<u32>
+}
+
+impl Mirror {
+    pub fn new(width: usize, height: usize, color: u32) -> Self {
+        let frame = Frame { generation: 0, width, height, buffer: vec![color; width * height] };
+        Mirror { shared: Arc::new((Mutex::new(frame), Condvar::new())) }
+    }
+
+    /// Replaces the frame with the content of the window `buffer` and wakes up waiting services.
+    pub fn publish(&self, buffer: &[u32], width: usize, height: usize) {
+        let (lock, cvar) = &*self.shared;
+        let mut frame = lock.lock().unwrap();
+        frame.buffer.clear();
+        frame.buffer.extend_from_slice(buffer);
+        frame.width = width;
+        frame.height = height;
+        frame.generation += 1;
+        cvar.notify_all();
+    }
+
+    /// Waits until a frame newer than `generation` is published or the `timeout` elapses,
+    /// returns the current generation.
+    pub fn wait_newer(&self, generation: u64, timeout: Duration) -> u64 {
+        let (lock, cvar) = &*self.shared;
+        let frame = lock.lock().unwrap();
+        let (frame, _) = cvar.wait_timeout_while(frame, timeout, |frame| frame.generation == generation)
+                             .unwrap();
+        frame.generation
+    }
+
+    /// Calls `f` with the current frame, the main loop is blocked meanwhile, so keep it short.
+    pub fn with_frame<R, F: FnOnce(&Frame) -> R>(&self, f: F) -> R {
+        f(&self.shared.0.lock().unwrap())
+    }
+}
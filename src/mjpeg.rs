@@ -0,0 +1,162 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+use image::{ColorType, RgbImage};
+use image::codecs::jpeg::JpegEncoder;
+
+use crate::mirror::{Frame, Mirror};
+
+/// The window contents are sent at least this often, even if unchanged.
+const KEEP_ALIVE: Duration = Duration::from_secs(2);
+/// The minimum duration between frames sent to a client.
+const MIN_FRAME_DURATION: Duration = Duration::from_millis(40);
+const MAX_CLIENTS: usize = 16;
+const BOUNDARY: &str = "rivframe";
+
+/*
+HTTP endpoints:
+
+GET /              - an MJPEG stream (multipart/x-mixed-replace) of the window contents
+GET /snapshot.jpg  - a single JPEG image of the window contents
+*/
+
+/// Encodes the mirrored window buffer, once per published frame for all the clients.
+struct Encoder {
+    mirror: Mirror,
+    quality: u8,
+    scale: f32,
+    last: Mutex<Option<(u64, Arc<Vec<u8>>)>>
+}
+
+impl Encoder {
+    /// Returns the JPEG of the frame published after `generation` or of the current one after the timeout.
+    fn next(&self, generation: u64, timeout: Duration) -> image::ImageResult<(u64, Arc<Vec<u8>>)> {
+        let generation = self.mirror.wait_newer(generation, timeout);
+        let mut last = self.last.lock().unwrap();
+        if let Some((encoded, jpeg)) = last.as_ref().filter(|(encoded, _)| *encoded == generation) {
+            return Ok((*encoded, jpeg.clone()))
+        }
+        // the main loop waits for the frame while it's copied, but not while it's scaled
+        let frame = self.mirror.with_frame(|frame| Frame {
+            generation: frame.generation,
+            width: frame.width,
+            height: frame.height,
+            buffer: frame.buffer.clone()
+        });
+        let (generation, img) = (frame.generation, scale_frame(&frame, self.scale));
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, self.quality)
+                    .encode(img.as_raw(), img.width(), img.height(), ColorType::Rgb8)?;
+        debug!("mjpeg frame {}: {} bytes", generation, jpeg.len());
+        let jpeg = Arc::new(jpeg);
+        *last = Some((generation, jpeg.clone()));
+        Ok((generation, jpeg))
+    }
+}
+
+/// Converts the frame to an image, scaled with the nearest neighbor.
+fn scale_frame(frame: &Frame, scale: f32) -> RgbImage {
+    let width = ((frame.width as f32 * scale).round() as u32).max(1);
+    let height = ((frame.height as f32 * scale).round() as u32).max(1);
+    RgbImage::from_fn(width, height, |x, y| {
+        let sx = (x as usize * frame.width / width as usize).min(frame.width - 1);
+        let sy = (y as usize * frame.height / height as usize).min(frame.height - 1);
+        let [_, r, g, b] = frame.buffer[sy * frame.width + sx].to_be_bytes();
+        image::Rgb([r, g, b])
+    })
+}
+
+pub fn bind(
+        address: SocketAddr,
+        mirror: Mirror,
+        quality: u8,
+        scale: f32
+    ) -> io::Result<()>
+{
+    let listener = TcpListener::bind(address)?;
+    debug!("mjpeg bind {:?}", listener.local_addr()?);
+    let encoder = Arc::new(Encoder { mirror, quality, scale, last: Mutex::new(None) });
+    let clients = Arc::new(AtomicUsize::new(0));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!("mjpeg accept failed: {}", err);
+                    continue;
+                }
+            };
+            if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                clients.fetch_sub(1, Ordering::SeqCst);
+                warn!("too many mjpeg clients, rejecting {:?}", stream.peer_addr());
+                continue;
+            }
+            let encoder = encoder.clone();
+            let clients = clients.clone();
+            thread::spawn(move || {
+                if let Err(err) = serve(stream, &encoder) {
+                    debug!("mjpeg client: {}", err);
+                }
+                clients.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn serve(mut stream: TcpStream, encoder: &Encoder) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // skip headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    debug!("mjpeg {} {} {}", peer, method, path);
+    let to_io = io::Error::other;
+    match (method, path.split('?').next().unwrap_or("")) {
+        ("GET", "/") => {
+            write!(stream, "HTTP/1.0 200 OK\r\n\
+                            Content-Type: multipart/x-mixed-replace; boundary={}\r\n\
+                            Cache-Control: no-cache\r\n\
+                            Connection: close\r\n\r\n", BOUNDARY)?;
+            let mut generation = u64::MAX;
+            loop {
+                let start = Instant::now();
+                let (next, jpeg) = encoder.next(generation, KEEP_ALIVE).map_err(to_io)?;
+                generation = next;
+                write!(stream, "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                       BOUNDARY, jpeg.len())?;
+                stream.write_all(&jpeg)?;
+                stream.write_all(b"\r\n")?;
+                stream.flush()?;
+                if let Some(rest) = MIN_FRAME_DURATION.checked_sub(start.elapsed()) {
+                    thread::sleep(rest);
+                }
+            }
+        }
+        ("GET", "/snapshot.jpg") => {
+            let (_, jpeg) = encoder.next(u64::MAX, Duration::ZERO).map_err(to_io)?;
+            write!(stream, "HTTP/1.0 200 OK\r\n\
+                            Content-Type: image/jpeg\r\n\
+                            Content-Length: {}\r\n\
+                            Cache-Control: no-cache\r\n\
+                            Connection: close\r\n\r\n", jpeg.len())?;
+            stream.write_all(&jpeg)
+        }
+        ("GET", _) => stream.write_all(b"HTTP/1.0 404 Not Found\r\nConnection: close\r\n\r\n"),
+        _ => stream.write_all(b"HTTP/1.0 405 Method Not Allowed\r\nConnection: close\r\n\r\n")
+    }
+}
@@ -1,12 +1,18 @@
+use std::net::SocketAddr;
+use std::time::SystemTime;
+use chrono::{DateTime, Utc};
 use clap::Arg;
 use css_color_parser::Color as CssColor;
 use std::process::Command;
-use crate::images;
-use crate::utils::Result;
+use crate::{images, patterns, qr, remote, widgets};
+use crate::utils::{self, Result};
 
-const DEFAULT_ADDRESS: &str = "localhost";
-// only if remote is not localhost
-const PUBLIC_BIND_ADDRESS: &str = "0.0.0.0";
+pub const DEFAULT_ADDRESS: &str = "localhost";
+// only if remote is not localhost, binds dual-stack
+const PUBLIC_BIND_ADDRESS: &str = "::";
+// only with an IPv4 multicast group
+const PUBLIC_BIND_ADDRESS_V4: &str = "0.0.0.0";
+const DEFAULT_OSC_PREFIX: &str = "/riv";
 
 pub trait AppArgs {
     fn app_args(self) -> Self;
@@ -16,7 +22,12 @@ impl<'a, 'b> AppArgs for clap::App<'a, 'b> {
     fn app_args(self) -> Self {
         self.arg(Arg::with_name("color").short("c").long("color").value_name("css")
             .env("RIV_WINDOW_COLOR")
-            .help("Window background color")
+            .help("Window background color, auto for the average color of the image edges")
+            .takes_value(true))
+        .arg(Arg::with_name("background").long("background").value_name("spec")
+            .env("RIV_BACKGROUND")
+            .help("Drawn behind the image instead of the color: linear-gradient(...), radial-gradient(...), \
+                   url(FILE) tiled or blur for a blurred copy of the image")
             .takes_value(true))
         .arg(Arg::with_name("xwin").short("x").long("xwin")
             .env("RIV_WINDOW_X")
@@ -46,22 +57,218 @@ impl<'a, 'b> AppArgs for clap::App<'a, 'b> {
             .env("RIV_REMOTE_ADDR")
             .help("Remote process IP address")
             .takes_value(true))
+        .arg(Arg::with_name("group").short("g").long("group").value_name("ipaddr")
+            .env("RIV_GROUP")
+            .help("Multicast group to join or to send commands to")
+            .takes_value(true))
+        .arg(Arg::with_name("id").long("id").value_name("name")
+            .env("RIV_ID")
+            .help("Instance ID reported in responses")
+            .takes_value(true))
+        .arg(Arg::with_name("osc").short("o").long("osc").value_name("port")
+            .env("RIV_OSC_PORT")
+            .help("Listen for OSC messages on UDP port")
+            .takes_value(true))
+        .arg(Arg::with_name("oscprefix").long("osc-prefix").value_name("path")
+            .env("RIV_OSC_PREFIX")
+            .help("OSC address prefix")
+            .default_value(DEFAULT_OSC_PREFIX))
+        .arg(Arg::with_name("mjpeg").long("mjpeg").value_name("port")
+            .env("RIV_MJPEG_PORT")
+            .help("Serve the window contents as an MJPEG stream over HTTP on TCP port")
+            .takes_value(true))
+        .arg(Arg::with_name("mjpegquality").long("mjpeg-quality").value_name("1-100")
+            .env("RIV_MJPEG_QUALITY")
+            .help("JPEG quality of the MJPEG stream")
+            .default_value("75"))
+        .arg(Arg::with_name("mjpegscale").long("mjpeg-scale").value_name("factor")
+            .env("RIV_MJPEG_SCALE")
+            .help("Scale of the MJPEG stream relative to the window size")
+            .default_value("1"))
+        .arg(Arg::with_name("vnc").long("vnc").value_name("port")
+            .env("RIV_VNC_PORT")
+            .help("Serve the window contents to VNC clients on TCP port")
+            .takes_value(true))
+        .arg(Arg::with_name("scale").long("scale").value_name("mode")
+            .env("RIV_SCALE")
+            .help("How images are fitted into the window: none, fit, down, fill, stretch or integer")
+            .default_value("none"))
+        .arg(Arg::with_name("grid").long("grid").value_name("dir")
+            .env("RIV_GRID")
+            .help("Shows the thumbnails of the images in the directory")
+            .conflicts_with_all(&["FILE", "raw"])
+            .takes_value(true))
+        .arg(Arg::with_name("qr").long("qr").value_name("text")
+            .env("RIV_QR")
+            .help("Shows the text encoded as a QR code")
+            .conflicts_with_all(&["FILE", "raw", "grid"])
+            .takes_value(true))
+        .arg(Arg::with_name("qrlevel").long("qr-level").value_name("L|M|Q|H")
+            .env("RIV_QR_LEVEL")
+            .help("QR code error correction level")
+            .default_value("M"))
+        .arg(Arg::with_name("qrmargin").long("qr-margin").value_name("modules")
+            .env("RIV_QR_MARGIN")
+            .help("Width of the quiet zone around the QR code")
+            .default_value("4"))
+        .arg(Arg::with_name("qrcolor").long("qr-color").value_name("css")
+            .env("RIV_QR_COLOR")
+            .help("Color of the dark QR code modules")
+            .default_value("black"))
+        .arg(Arg::with_name("qrbackground").long("qr-background").value_name("css")
+            .env("RIV_QR_BACKGROUND")
+            .help("Color of the light QR code modules and the quiet zone")
+            .default_value("white"))
+        .arg(Arg::with_name("pattern").long("pattern").value_name("name")
+            .env("RIV_PATTERN")
+            .help("Shows a test pattern: bars, ramp, checker[:SIZE], grid[:SIZE], dots[:SIZE] or a solid red, green, \
+                   blue, white, black, cyan, magenta or yellow")
+            .conflicts_with_all(&["FILE", "raw", "grid", "qr"])
+            .takes_value(true))
+        .arg(Arg::with_name("regions").long("regions").value_name("grid|file")
+            .env("RIV_REGIONS")
+            .help("Divides the window into a grid of COLUMNSxROWS regions or into the regions read from a file")
+            .takes_value(true))
+        .arg(Arg::with_name("region").long("region").value_name("name")
+            .env("RIV_REGION")
+            .help("Shows the image in the named region instead of the whole window")
+            .takes_value(true))
+        .arg(Arg::with_name("align").long("align").value_name("anchor")
+            .env("RIV_ALIGN")
+            .help("Where images are placed in the window: top-left, top, top-right, left, center, right, \
+                   bottom-left, bottom or bottom-right")
+            .takes_value(true))
+        .arg(Arg::with_name("offset").long("offset").value_name("x,y")
+            .env("RIV_OFFSET")
+            .help("Moves the aligned image by the given window pixels")
+            .takes_value(true))
+        .arg(Arg::with_name("zoom").long("zoom").value_name("factor")
+            .env("RIV_ZOOM")
+            .help("Zooms the image after scaling")
+            .takes_value(true))
+        .arg(Arg::with_name("pan").long("pan").value_name("x,y")
+            .env("RIV_PAN")
+            .help("Shows the image point given in image pixels in the window center")
+            .takes_value(true))
+        .arg(Arg::with_name("text").long("text").value_name("text")
+            .env("RIV_TEXT")
+            .help("Caption drawn over the image, {name} is replaced with the file name and \\n breaks lines")
+            .takes_value(true))
+        .arg(Arg::with_name("font").long("font").value_name("file")
+            .env("RIV_FONT")
+            .help("TTF or OTF font file for the caption, a built-in bitmap font is used by default")
+            .takes_value(true))
+        .arg(Arg::with_name("fontsize").long("font-size").value_name("pixels")
+            .env("RIV_FONT_SIZE")
+            .help("Caption line height [default: 32]")
+            .takes_value(true))
+        .arg(Arg::with_name("textcolor").long("text-color").value_name("css")
+            .env("RIV_TEXT_COLOR")
+            .help("Caption color [default: white]")
+            .takes_value(true))
+        .arg(Arg::with_name("outline").long("outline").value_name("pixels")
+            .env("RIV_OUTLINE")
+            .help("Width of the outline around the caption glyphs [default: 0]")
+            .takes_value(true))
+        .arg(Arg::with_name("outlinecolor").long("outline-color").value_name("css")
+            .env("RIV_OUTLINE_COLOR")
+            .help("Caption outline color [default: black]")
+            .takes_value(true))
+        .arg(Arg::with_name("textanchor").long("text-anchor").value_name("anchor")
+            .env("RIV_TEXT_ANCHOR")
+            .help("Where the caption is placed in the window, see --align [default: bottom]")
+            .takes_value(true))
+        .arg(Arg::with_name("clock").long("clock").value_name("format")
+            .env("RIV_CLOCK")
+            .help("Shows a clock with the time formatted with strftime, e.g. %H:%M")
+            .takes_value(true))
+        .arg(Arg::with_name("timezone").long("timezone").value_name("zone")
+            .env("RIV_TIMEZONE")
+            .help("Time zone of the clock and the countdown: local, an IANA name, e.g. Europe/Warsaw, or +HH:MM")
+            .default_value("local"))
+        .arg(Arg::with_name("countdown").long("countdown").value_name("time")
+            .env("RIV_COUNTDOWN")
+            .help("Shows a countdown to the time: +SECONDS, YYYY-MM-DD HH:MM[:SS] or HH:MM[:SS]")
+            .takes_value(true))
+        .arg(Arg::with_name("widgetanchor").long("widget-anchor").value_name("anchor")
+            .env("RIV_WIDGET_ANCHOR")
+            .help("Where the clock, the countdown and the elapsed timer are placed, see --align [default: top-right]")
+            .takes_value(true))
+        .arg(Arg::with_name("title").long("title").value_name("text")
+            .env("RIV_TITLE")
+            .help("Window title, implies --borders")
+            .takes_value(true))
         .arg(Arg::with_name("timeout").short("t").long("timeout").value_name("seconds")
             .env("RIV_TIMEOUT")
             .help("Remote process respond timeout")
             .takes_value(true))
+        .arg(Arg::with_name("at").short("a").long("at").value_name("time")
+            .env("RIV_PRESENT_AT")
+            .help("Present the image at the given time: +milliseconds from now or UNIX epoch milliseconds")
+            .takes_value(true))
+        .arg(Arg::with_name("push").short("u").long("push")
+            .help("Sends the image file content to the remote process instead of its path"))
+        .arg(Arg::with_name("raw").long("raw").value_name("format:WxH[:stride]")
+            .env("RIV_RAW")
+            .help("The image is an uncompressed frame (rgb, rgba, bgr, bgra or gray), streamed when read from -")
+            .takes_value(true))
+        .arg(Arg::with_name("resize").long("resize")
+            .help("Resizes the remote window to the given width and height"))
         .arg(Arg::with_name("fail").short("f").long("fail")
             .help("Exits after failing to contact the remote process"))
         .arg(Arg::with_name("detach").short("d").long("detach")
             .help("Run window process in the background and print its PID"))
+        .arg(Arg::with_name("httptimeout").long("http-timeout").value_name("seconds")
+            .env("RIV_HTTP_TIMEOUT")
+            .help("Timeout for downloading images from http:// URLs")
+            .default_value("10"))
+        .arg(Arg::with_name("maxsize").long("max-size").value_name("megabytes")
+            .env("RIV_MAX_SIZE")
+            .help("Maximum size of an image downloaded from http:// URLs")
+            .default_value("64"))
+        .arg(Arg::with_name("cachedir").long("cache-dir").value_name("dir")
+            .env("RIV_CACHE_DIR")
+            .help("Directory for caching images downloaded from http:// URLs")
+            .takes_value(true))
+        .arg(Arg::with_name("nocache").long("no-cache")
+            .help("Do not cache images downloaded from http:// URLs"))
+        .arg(Arg::with_name("discover").long("discover")
+            .help("Lists window processes responding on the network and exits"))
+        .arg(Arg::with_name("json").long("json")
+            .requires("discover")
+            .help("Prints the list of discovered processes as JSON"))
+        .arg(Arg::with_name("vncinput").long("vnc-input")
+            .requires("vnc")
+            .help("Accepts keyboard input from VNC clients"))
+        .arg(Arg::with_name("headless").long("headless")
+            .help("Runs without a window, e.g. to serve the contents over VNC or MJPEG only"))
+        .arg(Arg::with_name("fullscreen").long("fullscreen")
+            .conflicts_with_all(&["borders", "resizable"])
+            .help("Covers the whole screen, the window size and position are ignored"))
+        .arg(Arg::with_name("borders").long("borders")
+            .help("Shows a normal window with borders and a title bar"))
+        .arg(Arg::with_name("resizable").long("resizable")
+            .help("Lets the window be resized, implies --borders"))
+        .arg(Arg::with_name("elapsed").long("elapsed")
+            .help("Shows the time elapsed since the window was opened"))
+        .arg(Arg::with_name("hud").long("hud")
+            .help("Shows the image information overlay, toggled with the I key"))
+        .arg(Arg::with_name("captions").long("captions")
+            .help("Shows the file names under the thumbnails with --grid"))
+        .arg(Arg::with_name("topmost").long("topmost")
+            .help("Keeps the window on top of other windows"))
         .arg(Arg::with_name("nkey").short("K").long("no-key")
             .help("Do not exit after pressing ESC key"))
+        .arg(Arg::with_name("keys").long("keys").value_name("file")
+            .env("RIV_KEYS")
+            .help("File with key bindings overriding the default ones")
+            .takes_value(true))
         .arg(Arg::with_name("info").short("i").long("info")
             .help("Prints information about the image"))
         .arg(Arg::with_name("mswinfreecons").long("mswin-free-console")
             .hidden(true))
         .arg(Arg::with_name("FILE")
-            .help("An image file to display")
+            .help("An image file, a file:// or http:// URL or - for the standard input to display")
             .required(false))
     }
 }
@@ -74,13 +281,67 @@ pub struct Config<'a> {
     pub height: usize,
     pub width: usize,
     pub color: u32,
+    /// The background color is computed from the edges of each image.
+    pub auto_color: bool,
+    pub background: Option<&'a str>,
+    pub scale: images::ScaleMode,
+    pub grid: &'a str,
+    pub captions: bool,
+    pub qr: Option<&'a str>,
+    pub qr_style: qr::QrStyle,
+    pub pattern: Option<patterns::Pattern>,
+    pub hud: bool,
+    pub regions: &'a str,
+    pub region: &'a str,
+    pub align: Option<images::Align>,
+    pub offset: Option<(f64, f64)>,
+    pub zoom: Option<f64>,
+    pub pan: Option<(f64, f64)>,
+    pub text: Option<&'a str>,
+    pub font: &'a str,
+    pub font_size: Option<f32>,
+    pub text_color: Option<u32>,
+    pub outline: Option<f32>,
+    pub outline_color: Option<u32>,
+    pub text_anchor: Option<images::Align>,
+    pub clock: Option<&'a str>,
+    pub timezone: widgets::Zone,
+    pub countdown: Option<DateTime<Utc>>,
+    pub elapsed: bool,
+    pub widget_anchor: Option<images::Align>,
     pub port: u16,
+    pub osc_port: Option<u16>,
+    pub osc_prefix: &'a str,
+    pub mjpeg_port: Option<u16>,
+    pub mjpeg_quality: u8,
+    pub mjpeg_scale: f32,
+    pub vnc_port: Option<u16>,
     pub timeout: u64,
+    pub present_at: Option<SystemTime>,
+    pub http_timeout: u64,
+    pub max_size: u64,
+    pub cache_dir: &'a str,
+    pub no_cache: bool,
     pub remote: &'a str,
     pub bind: &'a str,
+    pub group: Option<SocketAddr>,
+    pub id: &'a str,
     pub nkey: bool,
+    pub keys: &'a str,
     pub fail: bool,
+    pub push: bool,
+    pub resize: bool,
+    pub raw: Option<images::RawFormat>,
     pub detach: bool,
+    pub discover: bool,
+    pub json: bool,
+    pub fullscreen: bool,
+    pub borders: bool,
+    pub resizable: bool,
+    pub topmost: bool,
+    pub title: &'a str,
+    pub vnc_input: bool,
+    pub headless: bool,
     pub info: bool,
     pub mswin_free_console: bool,
 }
@@ -89,42 +350,146 @@ impl<'a> Config<'a> {
     pub fn new<'b: 'a>(matches: &'b clap::ArgMatches<'a>) -> Result<Config<'a>> {
         let remote = matches.value_of("remote").unwrap_or(DEFAULT_ADDRESS);
         let fail = matches.is_present("fail");
+        let discover = matches.is_present("discover");
+        let port = matches.value_of("port").map(|v| v.parse()).transpose()
+                          .map_err(|_| "port must be an integer: 0 - 65535")?
+                          .unwrap();
+        let group = matches.value_of("group").map(|v| remote::resolve(v, port).map(|addrs| addrs[0]))
+                           .transpose()
+                           .map_err(|_| "group must be an IP address")?;
+        if group.is_some_and(|group| !group.ip().is_multicast()) {
+            return Err("group must be a multicast IP address".into())
+        }
+        // the countdown target time may be given in the time zone
+        let timezone: widgets::Zone = matches.value_of("timezone").unwrap().parse()?;
         Ok(Config {
-            width: matches.value_of("width").map(|v| v.parse()).transpose()
-                                      .map_err(|_| "width must be a positive integer")?
-                                      .unwrap(),
-            height: matches.value_of("height").map(|v| v.parse()).transpose()
-                                       .map_err(|_| "height must be a positive integer")?
-                                       .unwrap(),
+            width: matches.value_of("width").and_then(|v| v.parse().ok())
+                             .filter(|side| (1..=utils::MAX_WINDOW_SIZE).contains(side))
+                             .ok_or("width must be an integer: 1 - 16384")?,
+            height: matches.value_of("height").and_then(|v| v.parse().ok())
+                              .filter(|side| (1..=utils::MAX_WINDOW_SIZE).contains(side))
+                              .ok_or("height must be an integer: 1 - 16384")?,
             xwin: matches.value_of("xwin").map(|v| v.parse()).transpose()
                                      .map_err(|_| "xwin must be an integer")?
                                      .unwrap_or(0),
             ywin: matches.value_of("ywin").map(|v| v.parse()).transpose()
                                      .map_err(|_| "ywin must be an integer")?
                                      .unwrap_or(0),
-            color: matches.value_of("color")
+            color: matches.value_of("color").filter(|c| !c.eq_ignore_ascii_case("auto"))
                                .map(|c| c.parse::<CssColor>()).transpose()
                                .map_err(|_| "couldn't recognize a color name")?
                                .map(|CssColor { r, g, b, .. }| images::from_u8_rgb(r, g, b))
                                .unwrap_or(0),
-            port: matches.value_of("port").map(|v| v.parse()).transpose()
-                               .map_err(|_| "port must be an integer: 0 - 65535")?
-                               .unwrap(),
+            auto_color: matches.value_of("color").is_some_and(|c| c.eq_ignore_ascii_case("auto")),
+            background: matches.value_of("background"),
+            scale: matches.value_of("scale").unwrap().parse()?,
+            grid: matches.value_of("grid").unwrap_or(""),
+            captions: matches.is_present("captions"),
+            qr: matches.value_of("qr"),
+            qr_style: qr::QrStyle {
+                level: qr::parse_level(matches.value_of("qrlevel").unwrap())?,
+                margin: matches.value_of("qrmargin").map(|v| v.parse()).transpose().ok().flatten()
+                                          .filter(|margin| *margin <= qr::MAX_MARGIN)
+                                          .ok_or("qr margin must be an integer: 0 - 64")?,
+                color: matches.value_of("qrcolor").and_then(utils::parse_color)
+                                        .ok_or("couldn't recognize a qr color name")?,
+                background: matches.value_of("qrbackground").and_then(utils::parse_color)
+                                             .ok_or("couldn't recognize a qr background color name")?
+            },
+            pattern: matches.value_of("pattern").map(|v| v.parse()).transpose()?,
+            hud: matches.is_present("hud"),
+            regions: matches.value_of("regions").unwrap_or(""),
+            region: matches.value_of("region").unwrap_or(""),
+            align: matches.value_of("align").map(|v| v.parse()).transpose()?,
+            offset: matches.value_of("offset").map(|v| utils::parse_point(v).ok_or("offset must be a point: x,y"))
+                                         .transpose()?,
+            zoom: matches.value_of("zoom").map(|v| v.parse().ok().filter(|zoom: &f64| *zoom > 0.0 && zoom.is_finite())
+                                                   .ok_or("zoom must be a positive number"))
+                                     .transpose()?,
+            pan: matches.value_of("pan").map(|v| utils::parse_point(v).ok_or("pan must be a point: x,y"))
+                                   .transpose()?,
+            text: matches.value_of("text"),
+            font: matches.value_of("font").unwrap_or(""),
+            font_size: matches.value_of("fontsize").map(|v| v.parse().ok().filter(|size: &f32| *size > 0.0 && size.is_finite())
+                                                             .ok_or("font size must be a positive number"))
+                                          .transpose()?,
+            text_color: matches.value_of("textcolor").map(|v| utils::parse_color(v)
+                                                              .ok_or("couldn't recognize a text color name"))
+                                           .transpose()?,
+            outline: matches.value_of("outline").map(|v| v.parse().ok().filter(|width: &f32| *width >= 0.0)
+                                                          .ok_or("outline must be a non-negative number"))
+                                        .transpose()?,
+            outline_color: matches.value_of("outlinecolor").map(|v| utils::parse_color(v)
+                                                                    .ok_or("couldn't recognize an outline color name"))
+                                              .transpose()?,
+            text_anchor: matches.value_of("textanchor").map(|v| v.parse()).transpose()?,
+            clock: matches.value_of("clock").map(|v| Some(v).filter(|v| widgets::is_valid_format(v))
+                                                    .ok_or("clock format must be a valid strftime format"))
+                                       .transpose()?,
+            timezone,
+            countdown: matches.value_of("countdown").map(|v| widgets::parse_target(v, timezone)).transpose()?,
+            elapsed: matches.is_present("elapsed"),
+            widget_anchor: matches.value_of("widgetanchor").map(|v| v.parse()).transpose()?,
+            port,
+            osc_port: matches.value_of("osc").map(|v| v.parse()).transpose()
+                                      .map_err(|_| "osc port must be an integer: 0 - 65535")?,
+            osc_prefix: matches.value_of("oscprefix").unwrap(),
+            mjpeg_port: matches.value_of("mjpeg").map(|v| v.parse()).transpose()
+                                          .map_err(|_| "mjpeg port must be an integer: 0 - 65535")?,
+            mjpeg_quality: matches.value_of("mjpegquality").map(|v| v.parse()).transpose()
+                                                .ok().flatten()
+                                                .filter(|q| (1..=100).contains(q))
+                                                .ok_or("mjpeg quality must be an integer: 1 - 100")?,
+            mjpeg_scale: matches.value_of("mjpegscale").map(|v| v.parse()).transpose()
+                                            .ok().flatten()
+                                            .filter(|s| *s > 0.0 && *s <= 1.0)
+                                            .ok_or("mjpeg scale must be a number: 0 - 1")?,
+            vnc_port: matches.value_of("vnc").map(|v| v.parse()).transpose()
+                                      .map_err(|_| "vnc port must be an integer: 0 - 65535")?,
             remote,
-            bind: matches.value_of("bind").unwrap_or_else(|| if remote == DEFAULT_ADDRESS {
+            bind: matches.value_of("bind").unwrap_or_else(|| if remote == DEFAULT_ADDRESS && group.is_none() && !discover {
                 DEFAULT_ADDRESS
             }
+            else if group.is_some_and(|group| group.is_ipv4()) {
+                PUBLIC_BIND_ADDRESS_V4
+            }
             else {
                 PUBLIC_BIND_ADDRESS
             }),
+            group,
+            id: matches.value_of("id").unwrap_or(""),
             fail,
+            push: matches.is_present("push"),
+            resize: matches.is_present("resize"),
+            raw: matches.value_of("raw").map(|v| v.parse()).transpose()?,
             nkey: matches.is_present("nkey"),
+            keys: matches.value_of("keys").unwrap_or(""),
             detach: matches.is_present("detach"),
+            discover,
+            json: matches.is_present("json"),
+            fullscreen: matches.is_present("fullscreen"),
+            borders: matches.is_present("borders") || matches.is_present("resizable") ||
+                     (matches.is_present("title") && !matches.is_present("fullscreen")),
+            resizable: matches.is_present("resizable"),
+            topmost: matches.is_present("topmost"),
+            title: matches.value_of("title").unwrap_or(""),
+            vnc_input: matches.is_present("vncinput"),
+            headless: matches.is_present("headless"),
             info: matches.is_present("info"),
             mswin_free_console: matches.is_present("mswinfreecons"),
             timeout: matches.value_of("timeout").map(|v| v.parse()).transpose()
                                       .map_err(|_| "timeout must be a positive integer")?
-                                      .unwrap_or_else(|| if fail { 5 } else { 1 }),
+                                      .unwrap_or(if fail { 5 } else { 1 }),
+            present_at: matches.value_of("at").map(utils::parse_time).transpose()
+                                          .map_err(|_| "presentation time must be +milliseconds or milliseconds since epoch")?,
+            http_timeout: matches.value_of("httptimeout").map(|v| v.parse()).transpose()
+                                              .map_err(|_| "http timeout must be a positive integer")?
+                                              .unwrap(),
+            max_size: matches.value_of("maxsize").map(|v| v.parse()).transpose()
+                                     .map_err(|_| "max size must be a positive integer")?
+                                     .unwrap(),
+            cache_dir: matches.value_of("cachedir").unwrap_or(""),
+            no_cache: matches.is_present("nocache"),
             name: matches.value_of("FILE").unwrap_or("")
         })
     }
@@ -145,24 +510,163 @@ impl ArgsFrom for Command {
         arg_val("-h", &opts.height.to_string());
         arg_val("-t", &opts.timeout.to_string());
         arg_val("-p", &opts.port.to_string());
+        if let Some(osc_port) = opts.osc_port {
+            arg_val("-o", &osc_port.to_string());
+        }
+        if opts.osc_prefix != DEFAULT_OSC_PREFIX {
+            arg_val("--osc-prefix", opts.osc_prefix);
+        }
+        if let Some(mjpeg_port) = opts.mjpeg_port {
+            arg_val("--mjpeg", &mjpeg_port.to_string());
+        }
+        arg_val("--mjpeg-quality", &opts.mjpeg_quality.to_string());
+        arg_val("--mjpeg-scale", &opts.mjpeg_scale.to_string());
+        if let Some(vnc_port) = opts.vnc_port {
+            arg_val("--vnc", &vnc_port.to_string());
+        }
+        arg_val("--scale", opts.scale.name());
+        if !opts.grid.is_empty() {
+            arg_val("--grid", opts.grid);
+        }
+        if let Some(text) = opts.qr {
+            arg_val("--qr", text);
+        }
+        arg_val("--qr-level", qr::level_name(opts.qr_style.level));
+        arg_val("--qr-margin", &opts.qr_style.margin.to_string());
+        arg_val("--qr-color", &format!("#{:06x}", opts.qr_style.color));
+        arg_val("--qr-background", &format!("#{:06x}", opts.qr_style.background));
+        if let Some(pattern) = opts.pattern {
+            arg_val("--pattern", &pattern.to_string());
+        }
+        if !opts.regions.is_empty() {
+            arg_val("--regions", opts.regions);
+        }
+        if !opts.region.is_empty() {
+            arg_val("--region", opts.region);
+        }
+        if let Some(align) = opts.align {
+            arg_val("--align", align.name());
+        }
+        if let Some((x, y)) = opts.offset {
+            arg_val("--offset", &format!("{},{}", x, y));
+        }
+        if let Some(zoom) = opts.zoom {
+            arg_val("--zoom", &zoom.to_string());
+        }
+        if let Some((x, y)) = opts.pan {
+            arg_val("--pan", &format!("{},{}", x, y));
+        }
+        if let Some(text) = opts.text {
+            arg_val("--text", text);
+        }
+        if !opts.font.is_empty() {
+            arg_val("--font", opts.font);
+        }
+        if let Some(size) = opts.font_size {
+            arg_val("--font-size", &size.to_string());
+        }
+        if let Some(color) = opts.text_color {
+            arg_val("--text-color", &format!("#{:06x}", color));
+        }
+        if let Some(width) = opts.outline {
+            arg_val("--outline", &width.to_string());
+        }
+        if let Some(color) = opts.outline_color {
+            arg_val("--outline-color", &format!("#{:06x}", color));
+        }
+        if let Some(anchor) = opts.text_anchor {
+            arg_val("--text-anchor", anchor.name());
+        }
+        if let Some(format) = opts.clock {
+            arg_val("--clock", format);
+        }
+        arg_val("--timezone", &opts.timezone.to_string());
+        if let Some(target) = opts.countdown {
+            arg_val("--countdown", &target.to_rfc3339());
+        }
+        if let Some(anchor) = opts.widget_anchor {
+            arg_val("--widget-anchor", anchor.name());
+        }
+        if !opts.title.is_empty() {
+            arg_val("--title", opts.title);
+        }
+        if let Some(raw) = opts.raw {
+            arg_val("--raw", &raw.to_string());
+        }
+        if let Some(at) = opts.present_at {
+            arg_val("-a", &utils::epoch_millis(at).to_string());
+        }
+        arg_val("--http-timeout", &opts.http_timeout.to_string());
+        arg_val("--max-size", &opts.max_size.to_string());
+        if !opts.cache_dir.is_empty() {
+            arg_val("--cache-dir", opts.cache_dir);
+        }
+        if !opts.keys.is_empty() {
+            arg_val("--keys", opts.keys);
+        }
         if opts.xwin != 0 {
             arg_val("-x", &opts.xwin.to_string());
         }
         if opts.ywin != 0 {
             arg_val("-y", &opts.ywin.to_string());
         }
-        if opts.color != 0 {
+        if opts.auto_color {
+            arg_val("-c", "auto");
+        }
+        else if opts.color != 0 {
             arg_val("-c", &format!("#{:06x}", opts.color));
         }
+        if let Some(background) = opts.background {
+            arg_val("--background", background);
+        }
         if opts.remote != DEFAULT_ADDRESS {
             arg_val("-r", opts.remote);
         }
         if opts.bind != DEFAULT_ADDRESS {
             arg_val("-b", opts.bind);
         }
+        if let Some(group) = opts.group {
+            arg_val("-g", &utils::format_ip(group));
+        }
+        if !opts.id.is_empty() {
+            arg_val("--id", opts.id);
+        }
         if opts.fail {
             self.arg("-f");
         }
+        if opts.no_cache {
+            self.arg("--no-cache");
+        }
+        if opts.push {
+            self.arg("-u");
+        }
+        if opts.vnc_input {
+            self.arg("--vnc-input");
+        }
+        if opts.headless {
+            self.arg("--headless");
+        }
+        if opts.fullscreen {
+            self.arg("--fullscreen");
+        }
+        if opts.borders {
+            self.arg("--borders");
+        }
+        if opts.resizable {
+            self.arg("--resizable");
+        }
+        if opts.captions {
+            self.arg("--captions");
+        }
+        if opts.hud {
+            self.arg("--hud");
+        }
+        if opts.elapsed {
+            self.arg("--elapsed");
+        }
+        if opts.topmost {
+            self.arg("--topmost");
+        }
         if opts.nkey {
             self.arg("-K");
         }
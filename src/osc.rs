@@ -0,0 +1,372 @@
+use core::convert::TryInto;
+use core::result;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::thread;
+use log::{debug, warn};
+use css_color_parser::Color as CssColor;
+
+use crate::backgrounds::Background;
+use crate::images::{Loader, from_u8_rgb};
+use crate::remote::{Display, MAX_PACKET_SIZE, bind_socket};
+use crate::utils::MAX_WINDOW_SIZE;
+
+/*
+OSC 1.0 messages and bundles are accepted, with the following addresses:
+
+<prefix>/show ,s path        - display an image
+<prefix>/show ,ss path name  - display an image in the named region
+<prefix>/color ,iii r g b    - change background color (integers: 0 - 255)
+<prefix>/color ,fff r g b    - change background color (floats: 0.0 - 1.0)
+<prefix>/color ,s css        - change background color (CSS color)
+<prefix>/clear               - clear the window with the background color
+<prefix>/size ,ii w h        - resize the window
+<prefix>/align ,s align      - align the image, see images::Align
+<prefix>/offset ,ff x y      - move the aligned image by the given window pixels (also ,ii)
+<prefix>/zoom ,f zoom        - zoom the image (also ,i)
+<prefix>/pan ,ff x y         - show the image point given in image pixels in the window center (also ,ii)
+<prefix>/text ,s text        - draw the caption over the image, see remote.rs
+<prefix>/pattern ,s name     - show a test pattern, see patterns::Pattern
+<prefix>/background ,s spec  - draw a gradient, a tiled image or the blurred image behind the image, see backgrounds.rs
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+enum OscArg<'a> {
+    Int(i64),
+    Float(f64),
+    Str(&'a str),
+    Other
+}
+
+impl OscArg<'_> {
+    fn as_u8(&self) -> Option<u8> {
+        match *self {
+            OscArg::Int(v) => Some(v.clamp(0, 255) as u8),
+            OscArg::Float(v) => Some((v.clamp(0.0, 1.0) * 255.0).round() as u8),
+            _ => None
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            OscArg::Int(v) => Some(v as f64),
+            OscArg::Float(v) => Some(v),
+            _ => None
+        }
+    }
+}
+
+struct OscMessage<'a> {
+    address: &'a str,
+    args: Vec<OscArg<'a>>
+}
+
+pub fn bind(
+        address: SocketAddr,
+        prefix: &str,
+        color: u32,
+        loader: Loader,
+        main_send: Sender<Display>
+    ) -> std::io::Result<()>
+{
+    let socket = bind_socket(address)?;
+    debug!("osc bind {:?}", socket.local_addr()?);
+    let prefix = prefix.trim_end_matches('/').to_string();
+
+    thread::spawn(move || {
+        let mut udpbuf = [0; MAX_PACKET_SIZE];
+        let mut last_color = color;
+        let mut last_name = String::new();
+        loop {
+            let (amt, src) = match socket.recv_from(&mut udpbuf) {
+                Ok(msg) => msg,
+                Err(..) => continue
+            };
+            let mut messages = Vec::new();
+            if let Err(err) = parse_packet(&udpbuf[0..amt], &mut messages) {
+                debug!("invalid osc packet from {}, ignoring: {}", src, err);
+                continue;
+            }
+            for OscMessage { address, args } in messages {
+                let command = match address.strip_prefix(prefix.as_str()) {
+                    Some(command) => command,
+                    None => {
+                        debug!("osc address not ours: {}", address);
+                        continue;
+                    }
+                };
+                debug!("osc {} {:?}", command, args);
+                let mut resize = None;
+                let (mut align, mut offset, mut zoom, mut center) = (None, None, None, None);
+                let mut region = None;
+                let mut text = None;
+                let mut pattern = None;
+                let mut background = None;
+                let name = match (command, args.as_slice()) {
+                    ("/show", [OscArg::Str(name)]) => Some(*name),
+                    ("/show", [OscArg::Str(name), OscArg::Str(region_name)]) => {
+                        region = Some(region_name.to_string());
+                        Some(*name)
+                    }
+                    ("/color", [r, g, b]) => {
+                        match (r.as_u8(), g.as_u8(), b.as_u8()) {
+                            (Some(r), Some(g), Some(b)) => {
+                                last_color = from_u8_rgb(r, g, b);
+                                Some(last_name.as_str())
+                            }
+                            _ => None
+                        }
+                    }
+                    ("/color", [OscArg::Str(css)]) => {
+                        css.parse::<CssColor>().ok().map(|CssColor { r, g, b, .. }| {
+                            last_color = from_u8_rgb(r, g, b);
+                            last_name.as_str()
+                        })
+                    }
+                    ("/clear", []) => {
+                        last_name.clear();
+                        Some("")
+                    }
+                    ("/size", [OscArg::Int(width), OscArg::Int(height)]) => {
+                        // negative sides wrap around to too large sizes
+                        resize = Some((*width as usize, *height as usize)).filter(|&(width, height)| {
+                            (1..=MAX_WINDOW_SIZE).contains(&width) && (1..=MAX_WINDOW_SIZE).contains(&height)
+                        });
+                        if resize.is_none() {
+                            warn!("window size must be 1 - {}: {}x{}", MAX_WINDOW_SIZE, width, height);
+                        }
+                        resize.map(|_| last_name.as_str())
+                    }
+                    ("/align", [OscArg::Str(name)]) => {
+                        align = name.parse().ok();
+                        align.map(|_| last_name.as_str())
+                    }
+                    ("/offset", [x, y]) => {
+                        offset = x.as_f64().zip(y.as_f64());
+                        offset.map(|_| last_name.as_str())
+                    }
+                    ("/zoom", [factor]) => {
+                        zoom = factor.as_f64().filter(|factor| *factor > 0.0);
+                        zoom.map(|_| last_name.as_str())
+                    }
+                    ("/pan", [x, y]) => {
+                        center = x.as_f64().zip(y.as_f64());
+                        center.map(|_| last_name.as_str())
+                    }
+                    ("/text", [OscArg::Str(caption)]) => {
+                        text = Some(caption.to_string());
+                        Some(last_name.as_str())
+                    }
+                    ("/background", [OscArg::Str(spec)]) => {
+                        background = Background::parse(spec).map_err(|err| warn!("{}", err)).ok();
+                        background.as_ref().map(|_| last_name.as_str())
+                    }
+                    ("/pattern", [OscArg::Str(name)]) => {
+                        pattern = name.parse().ok();
+                        pattern.map(|_| {
+                            last_name.clear();
+                            ""
+                        })
+                    }
+                    _ => None
+                };
+                let name = match name {
+                    Some(name) => name,
+                    None => {
+                        warn!("unrecognized osc message: {} {:?}", address, args);
+                        continue;
+                    }
+                };
+                // the image shown in the whole window is reloaded by the following messages once it's loaded
+                let shown_name = (command == "/show" && region.is_none()).then(|| name.to_string());
+                let mut display = if name.is_empty() {
+                    Display::new(last_color, None)
+                }
+                else {
+                    debug!("loading: {}", name);
+                    match loader.load(name) {
+                        Ok((img, meta)) => Display {
+                            name: name.to_string(),
+                            meta: Some(meta),
+                            ..Display::new(last_color, Some(img))
+                        },
+                        Err(err) => {
+                            warn!("loading image failed: {}", err);
+                            continue;
+                        }
+                    }
+                };
+                display.resize = resize;
+                display.region = region;
+                display.align = align;
+                display.offset = offset;
+                display.zoom = zoom;
+                display.center = center;
+                display.text = text;
+                display.pattern = pattern;
+                display.background = background;
+                if let Some(name) = shown_name {
+                    last_name = name;
+                }
+                if main_send.send(display).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn parse_packet<'a>(data: &'a [u8], messages: &mut Vec<OscMessage<'a>>) -> result::Result<(), &'static str> {
+    if data.starts_with(b"#bundle\0") {
+        // skip the time tag, bundle elements are handled immediately
+        let mut data = data.get(16..).ok_or("bundle too short")?;
+        while !data.is_empty() {
+            let (size, rest) = read_i32(data)?;
+            let size: usize = size.try_into().map_err(|_| "negative bundle element size")?;
+            let element = rest.get(0..size).ok_or("wrong bundle element size")?;
+            parse_packet(element, messages)?;
+            data = &rest[size..];
+        }
+        return Ok(())
+    }
+    let (address, data) = read_str(data)?;
+    if !address.starts_with('/') {
+        return Err("invalid address pattern");
+    }
+    // a missing type tag string is tolerated by the spec
+    let (tags, mut data) = if data.is_empty() { (",", data) } else { read_str(data)? };
+    let tags = tags.strip_prefix(',').ok_or("invalid type tag string")?;
+    let mut args = Vec::with_capacity(tags.len());
+    for tag in tags.bytes() {
+        let arg = match tag {
+            b'i' => read_i32(data).map(|(v, rest)| { data = rest; OscArg::Int(v.into()) })?,
+            b'h' => read_i64(data).map(|(v, rest)| { data = rest; OscArg::Int(v) })?,
+            b'f' => read_i32(data).map(|(v, rest)| {
+                data = rest; OscArg::Float(f32::from_bits(v as u32).into())
+            })?,
+            b'd' => read_i64(data).map(|(v, rest)| {
+                data = rest; OscArg::Float(f64::from_bits(v as u64))
+            })?,
+            b's'|b'S' => read_str(data).map(|(v, rest)| { data = rest; OscArg::Str(v) })?,
+            b'b' => {
+                let (size, rest) = read_i32(data)?;
+                let size: usize = size.try_into().map_err(|_| "negative blob size")?;
+                data = rest.get(padded(size)..).ok_or("wrong blob size")?;
+                OscArg::Other
+            }
+            b't' => read_i64(data).map(|(_, rest)| { data = rest; OscArg::Other })?,
+            b'c'|b'r'|b'm' => read_i32(data).map(|(_, rest)| { data = rest; OscArg::Other })?,
+            b'T'|b'F'|b'N'|b'I' => OscArg::Other,
+            _ => return Err("unsupported type tag")
+        };
+        args.push(arg);
+    }
+    messages.push(OscMessage { address, args });
+    Ok(())
+}
+
+fn padded(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+fn read_str(data: &[u8]) -> result::Result<(&str, &[u8]), &'static str> {
+    let len = data.iter().position(|&b| b == 0).ok_or("unterminated string")?;
+    let s = core::str::from_utf8(&data[0..len]).map_err(|_| "can't decode UTF-8")?;
+    let rest = data.get(padded(len + 1)..).ok_or("string padding missing")?;
+    Ok((s, rest))
+}
+
+fn read_i32(data: &[u8]) -> result::Result<(i32, &[u8]), &'static str> {
+    if data.len() < 4 {
+        return Err("argument too short");
+    }
+    Ok((i32::from_be_bytes(data[0..4].try_into().unwrap()), &data[4..]))
+}
+
+fn read_i64(data: &[u8]) -> result::Result<(i64, &[u8]), &'static str> {
+    if data.len() < 8 {
+        return Err("argument too short");
+    }
+    Ok((i64::from_be_bytes(data[0..8].try_into().unwrap()), &data[8..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes the string padded with zeros to a multiple of 4 bytes.
+    fn osc_str(s: &str) -> Vec<u8> {
+        let mut data = s.as_bytes().to_vec();
+        data.resize(padded(s.len() + 1), 0);
+        data
+    }
+
+    fn parse(data: &[u8]) -> result::Result<Vec<(&str, Vec<OscArg<'_>>)>, &'static str> {
+        let mut messages = Vec::new();
+        parse_packet(data, &mut messages)?;
+        Ok(messages.into_iter().map(|OscMessage { address, args }| (address, args)).collect())
+    }
+
+    #[test]
+    fn parses_type_tags() {
+        let mut data = [osc_str("/riv/test"), osc_str(",ifsdhbTc")].concat();
+        data.extend_from_slice(&(-7i32).to_be_bytes());
+        data.extend_from_slice(&0.5f32.to_bits().to_be_bytes());
+        data.extend_from_slice(&osc_str("abc"));
+        data.extend_from_slice(&0.25f64.to_bits().to_be_bytes());
+        data.extend_from_slice(&(1i64 << 40).to_be_bytes());
+        data.extend_from_slice(&5i32.to_be_bytes());
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 0, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0, b'x']);
+        let messages = parse(&data).unwrap();
+        assert_eq!(messages, vec![("/riv/test", vec![OscArg::Int(-7), OscArg::Float(0.5), OscArg::Str("abc"),
+                                                     OscArg::Float(0.25), OscArg::Int(1 << 40), OscArg::Other,
+                                                     OscArg::Other, OscArg::Other])]);
+    }
+
+    #[test]
+    fn pads_strings_to_four_bytes() {
+        // the terminating zero of a 4 byte string takes another 4 bytes
+        for s in ["", "a", "abc", "abcd", "abcdefg"] {
+            let data = [osc_str("/riv/show"), osc_str(",s"), osc_str(s)].concat();
+            assert_eq!(parse(&data).unwrap(), vec![("/riv/show", vec![OscArg::Str(s)])]);
+            assert!(parse(&data[..data.len() - 1]).is_err());
+        }
+        assert_eq!(osc_str("abcd").len(), 8);
+    }
+
+    #[test]
+    fn tolerates_missing_type_tags() {
+        assert_eq!(parse(&osc_str("/riv/clear")).unwrap(), vec![("/riv/clear", vec![])]);
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(parse(&osc_str("riv/clear")).is_err());
+        assert!(parse(&[osc_str("/riv/clear"), osc_str("s")].concat()).is_err());
+        assert!(parse(&[osc_str("/riv/show"), osc_str(",s")].concat()).is_err());
+        assert!(parse(&[osc_str("/riv/size"), osc_str(",ii"), vec![0, 0, 0, 1, 0, 0]].concat()).is_err());
+        assert!(parse(&[osc_str("/riv/x"), osc_str(",q"), vec![0; 4]].concat()).is_err());
+        assert!(parse(b"/riv/show").is_err());
+        let mut blob = [osc_str("/riv/x"), osc_str(",b")].concat();
+        blob.extend_from_slice(&(-1i32).to_be_bytes());
+        assert!(parse(&blob).is_err());
+    }
+
+    #[test]
+    fn parses_bundles() {
+        let first = [osc_str("/riv/color"), osc_str(",s"), osc_str("red")].concat();
+        let second = osc_str("/riv/clear");
+        let mut data = [osc_str("#bundle"), vec![0; 8]].concat();
+        for element in [&first, &second] {
+            data.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            data.extend_from_slice(element);
+        }
+        assert_eq!(parse(&data).unwrap(), vec![("/riv/color", vec![OscArg::Str("red")]), ("/riv/clear", vec![])]);
+        let size = data.len() - second.len() - 4;
+        data[size..size + 4].copy_from_slice(&100i32.to_be_bytes());
+        assert!(parse(&data).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::text::{self, Canvas};
+
+/// The spacing of the checkerboard, the grid lines and the dots in pixels, unless given after the name.
+pub const DEFAULT_SPACING: usize = 64;
+const MAX_SPACING: usize = 4096;
+
+/// The solid patterns and their colors.
+const SOLID: [(&str, u32); 8] = [("red", 0xff0000), ("green", 0x00ff00), ("blue", 0x0000ff), ("white", 0xffffff),
+                                 ("black", 0), ("cyan", 0x00ffff), ("magenta", 0xff00ff), ("yellow", 0xffff00)];
+/// The 75% color bars: white, yellow, cyan, green, magenta, red and blue.
+const BARS: [u32; 7] = [0xbfbfbf, 0xbfbf00, 0x00bfbf, 0x00bf00, 0xbf00bf, 0xbf0000, 0x0000bf];
+/// The reverse bars under the color bars: blue, black, magenta, black, cyan, black and white.
+const REVERSE_BARS: [u32; 7] = [0x0000bf, 0x131313, 0xbf00bf, 0x131313, 0x00bfbf, 0x131313, 0xbfbfbf];
+/// The bottom row of the color bars: -I, white, +Q and black with the PLUGE steps: below, at and above black.
+const BOTTOM_BARS: [(u32, usize); 7] = [(0x00214c, 5), (0xffffff, 5), (0x32006a, 5), (0x131313, 5),
+                                        (0x090909, 1), (0x131313, 1), (0x1d1d1d, 1)];
+const LINE_COLOR: u32 = 0xffffff;
+const CROSSHAIR_COLOR: u32 = 0xff0000;
+
+/// A test pattern generated at the resolution of the window or the region it's shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// SMPTE-like 75% color bars with the reverse bars and the PLUGE steps.
+    Bars,
+    /// A continuous grayscale ramp over a ramp of 16 steps.
+    Ramp,
+    /// Black and white squares of the given size.
+    Checker(usize),
+    /// Lines at the given spacing from the top left corner, the window border, a crosshair in the center
+    /// and the size in pixels.
+    Grid(usize),
+    /// Single pixel dots at the given spacing, one of them in the center.
+    Dots(usize),
+    /// A single color, one of `SOLID`.
+    Solid(u32)
+}
+
+impl Pattern {
+    /// Fills the buffer with the pattern.
+    pub fn draw(self, buffer: &mut [u32], width: usize, height: usize) {
+        match self {
+            Pattern::Bars => draw_bars(buffer, width, height),
+            Pattern::Ramp => draw_ramp(buffer, width, height),
+            Pattern::Checker(size) => {
+                for (index, pixel) in buffer.iter_mut().enumerate() {
+                    let (x, y) = (index % width / size, index / width / size);
+                    *pixel = if (x + y) % 2 == 0 { 0xffffff } else { 0 };
+                }
+            }
+            Pattern::Grid(spacing) => draw_grid(buffer, width, height, spacing),
+            Pattern::Dots(spacing) => {
+                buffer.fill(0);
+                let (cx, cy) = (width / 2 % spacing, height / 2 % spacing);
+                for y in (cy..height).step_by(spacing) {
+                    for x in (cx..width).step_by(spacing) {
+                        buffer[y * width + x] = LINE_COLOR;
+                    }
+                }
+            }
+            Pattern::Solid(color) => buffer.fill(color)
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERR: &str = "pattern must be one of: bars, ramp, checker[:SIZE], grid[:SIZE], dots[:SIZE], \
+                           red, green, blue, white, black, cyan, magenta, yellow";
+        let (name, spacing) = match s.split_once(':') {
+            Some((name, spacing)) => {
+                let spacing = spacing.parse().ok().filter(|spacing| (1..=MAX_SPACING).contains(spacing))
+                                     .ok_or("pattern size must be an integer: 1 - 4096")?;
+                (name, Some(spacing))
+            }
+            None => (s, None)
+        };
+        let name = name.to_ascii_lowercase();
+        let pattern = match name.as_str() {
+            "checker" => Pattern::Checker(spacing.unwrap_or(DEFAULT_SPACING)),
+            "grid" => Pattern::Grid(spacing.unwrap_or(DEFAULT_SPACING)),
+            "dots" => Pattern::Dots(spacing.unwrap_or(DEFAULT_SPACING)),
+            _ if spacing.is_some() => return Err(ERR),
+            "bars" => Pattern::Bars,
+            "ramp" => Pattern::Ramp,
+            _ => SOLID.iter().find(|(solid, _)| *solid == name).map(|&(_, color)| Pattern::Solid(color)).ok_or(ERR)?
+        };
+        Ok(pattern)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Bars => f.write_str("bars"),
+            Pattern::Ramp => f.write_str("ramp"),
+            Pattern::Checker(size) => write!(f, "checker:{}", size),
+            Pattern::Grid(spacing) => write!(f, "grid:{}", spacing),
+            Pattern::Dots(spacing) => write!(f, "dots:{}", spacing),
+            Pattern::Solid(color) => {
+                f.write_str(SOLID.iter().find(|(_, solid)| solid == color).map_or("black", |(name, _)| name))
+            }
+        }
+    }
+}
+
+/// Fills the rows from `top` to `bottom` with bars of the given relative widths.
+fn fill_bars(buffer: &mut [u32], width: usize, top: usize, bottom: usize, bars: &[(u32, usize)]) {
+    let total: usize = bars.iter().map(|(_, weight)| weight).sum();
+    for row in buffer[top * width..bottom * width].chunks_exact_mut(width) {
+        let mut left = 0;
+        let mut weights = 0;
+        for &(color, weight) in bars {
+            weights += weight;
+            let right = weights * width / total;
+            row[left..right].fill(color);
+            left = right;
+        }
+    }
+}
+
+fn draw_bars(buffer: &mut [u32], width: usize, height: usize) {
+    let (bars_bottom, reverse_bottom) = (height * 2 / 3, height * 3 / 4);
+    fill_bars(buffer, width, 0, bars_bottom, &BARS.map(|color| (color, 1)));
+    fill_bars(buffer, width, bars_bottom, reverse_bottom, &REVERSE_BARS.map(|color| (color, 1)));
+    fill_bars(buffer, width, reverse_bottom, height, &BOTTOM_BARS);
+}
+
+fn draw_ramp(buffer: &mut [u32], width: usize, height: usize) {
+    let gray = |level: usize| level as u32 * 0x010101;
+    let max_x = width.saturating_sub(1).max(1);
+    for (y, row) in buffer.chunks_exact_mut(width).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = if y < height / 2 { gray(x * 255 / max_x) } else { gray(x * 16 / width * 255 / 15) };
+        }
+    }
+}
+
+fn draw_grid(buffer: &mut [u32], width: usize, height: usize, spacing: usize) {
+    buffer.fill(0);
+    if width == 0 || height == 0 {
+        return
+    }
+    let mut canvas = Canvas { buf: buffer, width, height };
+    for x in (0..width).step_by(spacing).chain(Some(width - 1)) {
+        canvas.fill_rect(x as isize, 0, 1, height, LINE_COLOR, 255);
+    }
+    for y in (0..height).step_by(spacing).chain(Some(height - 1)) {
+        canvas.fill_rect(0, y as isize, width, 1, LINE_COLOR, 255);
+    }
+    let (cx, cy) = (width as isize / 2, height as isize / 2);
+    canvas.fill_rect(cx, 0, 1, height, CROSSHAIR_COLOR, 255);
+    canvas.fill_rect(0, cy, width, 1, CROSSHAIR_COLOR, 255);
+    // the size is written in the top right quarter, next to the center
+    let label = format!("{}x{}", width, height);
+    let scale = (height / 360).max(1);
+    let (text_width, text_height) = text::text_size(&label, scale);
+    let (x, y) = (cx + 2 * scale as isize, cy - 2 * scale as isize - text_height as isize);
+    canvas.fill_rect(x - scale as isize, y - scale as isize, text_width + 2 * scale, text_height + 2 * scale, 0, 255);
+    canvas.draw_text(x, y, &label, LINE_COLOR, scale);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_patterns() {
+        assert_eq!("bars".parse(), Ok(Pattern::Bars));
+        assert_eq!("RAMP".parse(), Ok(Pattern::Ramp));
+        assert_eq!("checker".parse(), Ok(Pattern::Checker(DEFAULT_SPACING)));
+        assert_eq!("grid:10".parse(), Ok(Pattern::Grid(10)));
+        assert_eq!("Dots:1".parse(), Ok(Pattern::Dots(1)));
+        assert_eq!("grid:4096".parse(), Ok(Pattern::Grid(4096)));
+        assert_eq!("magenta".parse(), Ok(Pattern::Solid(0xff00ff)));
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        for pattern in ["", "stripes", "grid:", "grid:0", "grid:4097", "grid:-1", "grid:1.5", "checker:8:8",
+                        "bars:8", "red:8", "orange"] {
+            assert!(pattern.parse::<Pattern>().is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn formats_parsable_names() {
+        for pattern in [Pattern::Bars, Pattern::Ramp, Pattern::Checker(3), Pattern::Grid(64), Pattern::Dots(7),
+                        Pattern::Solid(0x00ffff)] {
+            assert_eq!(pattern.to_string().parse(), Ok(pattern));
+        }
+    }
+
+    #[test]
+    fn draws_at_tiny_sizes() {
+        for pattern in [Pattern::Bars, Pattern::Ramp, Pattern::Checker(4096), Pattern::Grid(1), Pattern::Dots(4096)] {
+            let mut buffer = vec![0; 3];
+            pattern.draw(&mut buffer, 1, 3);
+            pattern.draw(&mut buffer, 3, 1);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::Instant;
+use image::{ColorType, Rgb, RgbImage};
+use qrcode::{Color, EcLevel, QrCode};
+
+use crate::images::Metadata;
+use crate::utils::Result;
+
+/// The width of the quiet zone recommended by the QR code specification, in modules.
+pub const DEFAULT_MARGIN: u32 = 4;
+pub const MAX_MARGIN: u32 = 64;
+
+/// How a QR code is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QrStyle {
+    /// The error correction level: L, M, Q or H.
+    pub level: EcLevel,
+    /// The width of the quiet zone around the code, in modules.
+    pub margin: u32,
+    /// The colors of the dark and the light modules.
+    pub color: u32,
+    pub background: u32
+}
+
+impl Default for QrStyle {
+    fn default() -> Self {
+        QrStyle { level: EcLevel::M, margin: DEFAULT_MARGIN, color: 0, background: 0xffffff }
+    }
+}
+
+pub fn parse_level(level: &str) -> core::result::Result<EcLevel, &'static str> {
+    Ok(match level.to_ascii_uppercase().as_str() {
+        "L" => EcLevel::L,
+        "M" => EcLevel::M,
+        "Q" => EcLevel::Q,
+        "H" => EcLevel::H,
+        _ => return Err("QR code error correction level must be one of: L, M, Q, H")
+    })
+}
+
+pub fn level_name(level: EcLevel) -> &'static str {
+    match level {
+        EcLevel::L => "L",
+        EcLevel::M => "M",
+        EcLevel::Q => "Q",
+        EcLevel::H => "H"
+    }
+}
+
+/// Encodes the text as a QR code image with one pixel per module, to be scaled by whole factors.
+pub fn render(text: &str, style: &QrStyle) -> Result<(RgbImage, Metadata)> {
+    let start = Instant::now();
+    let code = QrCode::with_error_correction_level(text, style.level)?;
+    let modules = code.width() as u32;
+    let size = modules + 2 * style.margin;
+    let [_, r, g, b] = style.background.to_be_bytes();
+    let mut img = RgbImage::from_pixel(size, size, Rgb([r, g, b]));
+    let [_, r, g, b] = style.color.to_be_bytes();
+    for (index, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let index = index as u32;
+            img.put_pixel(style.margin + index % modules, style.margin + index / modules, Rgb([r, g, b]));
+        }
+    }
+    let meta = Metadata {
+        format: format!("QR level {}", level_name(style.level)),
+        color_type: format!("{:?}", ColorType::Rgb8),
+        file_size: text.len() as u64,
+        decode_time: start.elapsed(),
+        exif: Vec::new(),
+        edge_color: style.background
+    };
+    Ok((img, meta))
+}
@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::Path;
+use css_color_parser::Color as CssColor;
+
+use crate::images::{self, Align, ScaleMode};
+use crate::remote::Display;
+use crate::text::Canvas;
+use crate::utils::{self, Result};
+
+/*
+A regions file contains lines: NAME = WIDTHxHEIGHT+X+Y [color=CSS] [scale=MODE] [align=ANCHOR], e.g.:
+
+# lines starting with a hash are comments
+before = 960x1080+0+0 color=black scale=fit
+after = 960x1080+960+0 color=#333 scale=fit
+logo = 200x100+1720+0 scale=down align=top-right
+
+The regions may overlap, later ones are drawn over the earlier ones.
+*/
+
+/// A named part of the window showing its own display.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub name: String,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    /// The background color, the color of the display is used if not set.
+    pub color: Option<u32>,
+    pub scale: ScaleMode,
+    /// The alignment of images which don't set their own.
+    pub align: Align,
+    /// The display shown in the region.
+    pub display: Option<Display>
+}
+
+/// The regions the window is divided into.
+#[derive(Debug, Clone, Default)]
+pub struct Regions {
+    /// The number of columns and rows of a grid laid out again when the window is resized.
+    grid: Option<(usize, usize)>,
+    pub list: Vec<Region>
+}
+
+impl Regions {
+    /// Creates a grid of regions named "1", "2", ... row by row, or reads the regions from a file.
+    pub fn new(spec: &str, scale: ScaleMode, align: Align, width: usize, height: usize) -> Result<Self> {
+        if let Some((columns, rows)) = utils::parse_size(spec).filter(|_| !Path::new(spec).is_file()) {
+            let list = (0..columns * rows).map(|index| Region {
+                name: (index + 1).to_string(),
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+                color: None,
+                scale,
+                align,
+                display: None
+            }).collect();
+            let mut regions = Regions { grid: Some((columns, rows)), list };
+            regions.resize(width, height);
+            return Ok(regions)
+        }
+        Regions::from_file(spec, scale, align).map_err(|err| format!("{}: {}", spec, err).into())
+    }
+
+    fn from_file(path: &str, scale: ScaleMode, align: Align) -> Result<Self> {
+        let mut list: Vec<Region> = Vec::new();
+        let content = fs::read_to_string(path)?;
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let err = |msg: &str| format!("line {}: {}", index + 1, msg);
+            let (name, rest) = line.split_once('=').map(|(name, rest)| (name.trim(), rest))
+                                   .filter(|(name, _)| !name.is_empty())
+                                   .ok_or_else(|| err("expected NAME = WIDTHxHEIGHT+X+Y"))?;
+            if list.iter().any(|region| region.name == name) {
+                return Err(err("duplicate region name").into())
+            }
+            let mut words = rest.split_whitespace();
+            let (width, height, x, y) = words.next().and_then(parse_geometry)
+                                             .ok_or_else(|| err("geometry must be WIDTHxHEIGHT+X+Y"))?;
+            let mut region = Region {
+                name: name.to_string(),
+                x,
+                y,
+                width,
+                height,
+                color: None,
+                scale,
+                align,
+                display: None
+            };
+            for word in words {
+                match word.split_once('=') {
+                    Some(("color", css)) => {
+                        let CssColor { r, g, b, .. } = css.parse().map_err(|_| err("couldn't recognize a color name"))?;
+                        region.color = Some(images::from_u8_rgb(r, g, b));
+                    }
+                    Some(("scale", mode)) => region.scale = mode.parse().map_err(err)?,
+                    Some(("align", anchor)) => region.align = anchor.parse().map_err(err)?,
+                    _ => return Err(err(&format!("unknown region option: {}", word)).into())
+                }
+            }
+            list.push(region);
+        }
+        if list.is_empty() {
+            return Err("no regions defined".into())
+        }
+        Ok(Regions { grid: None, list })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.list.iter().map(|region| region.name.clone()).collect()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.list.iter().position(|region| region.name == name)
+    }
+
+    /// Lays out the grid again for the new window size.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if let Some((columns, rows)) = self.grid {
+            for (index, region) in self.list.iter_mut().enumerate() {
+                let (column, row) = (index % columns, index / columns);
+                region.x = column * width / columns;
+                region.y = row * height / rows;
+                region.width = (column + 1) * width / columns - region.x;
+                region.height = (row + 1) * height / rows - region.y;
+            }
+        }
+    }
+
+    /// Removes the displays from all the regions.
+    pub fn clear(&mut self) {
+        for region in self.list.iter_mut() {
+            region.display = None;
+        }
+    }
+}
+
+impl Region {
+    /// Draws the `display` into the region of the window buffer, or fills the region with its color.
+    pub fn draw_into(&self, display: Option<&Display>, buffer: &mut [u32], buf_width: usize, buf_height: usize) {
+        if self.x >= buf_width || self.y >= buf_height || self.width == 0 || self.height == 0 {
+            return
+        }
+        let part = match (display, self.color) {
+            (Some(display), _) => {
+                let color = self.color.unwrap_or(display.color);
+                let mut part = vec![color; self.width * self.height];
+                if let Some(background) = display.background.as_ref().filter(|_| display.pattern.is_none()) {
+                    background.draw(display.image.as_ref(), color, &mut part, self.width, self.height);
+                }
+                if let Some(pattern) = display.pattern {
+                    pattern.draw(&mut part, self.width, self.height);
+                }
+                else if let Some(img) = display.image.as_ref() {
+                    let mut layout = images::Layout {
+                        align: display.align.unwrap_or(self.align),
+                        offset: display.offset.unwrap_or_default(),
+                        ..images::Layout::new(display.scale.unwrap_or(self.scale))
+                    };
+                    let buf_size = (self.width as u32, self.height as u32);
+                    if let Some(zoom) = display.zoom {
+                        layout.set_zoom(zoom);
+                    }
+                    if let Some(center) = display.center {
+                        layout.set_center(center, img.dimensions(), buf_size);
+                    }
+                    let bgpixel = display.background.is_none().then_some(color);
+                    images::compose_into(img, &layout, bgpixel, buf_size.0, buf_size.1, &mut part);
+                }
+                if let Some(caption) = display.caption() {
+                    Canvas { buf: &mut part, width: self.width, height: self.height }
+                        .draw_caption(&caption, &display.text_style);
+                }
+                part
+            }
+            (None, Some(color)) => vec![color; self.width * self.height],
+            (None, None) => return
+        };
+        images::copy_into(&part, self.width, self.x, self.y, buf_width, buf_height, buffer);
+    }
+}
+
+/// Parses a region geometry: "WIDTHxHEIGHT+X+Y".
+fn parse_geometry(value: &str) -> Option<(usize, usize, usize, usize)> {
+    let (size, position) = value.split_once('+')?;
+    let (width, height) = utils::parse_size(size)?;
+    let (x, y) = position.split_once('+')?;
+    Some((width, height, x.parse().ok()?, y.parse().ok()?))
+}
@@ -1,22 +1,139 @@
-use core::fmt::Debug;
 use core::{mem, result};
 use core::convert::TryInto;
-use std::net::{ToSocketAddrs, SocketAddr, UdpSocket};
-use std::sync::mpsc::{channel, TryRecvError, Sender, Receiver};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, ToSocketAddrs, SocketAddr, SocketAddrV6, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, TryRecvError, Sender};
 use std::thread;
-use std::time::{Duration, Instant};
-use log::{Level, debug, warn, log_enabled};
+use std::time::{Duration, Instant, SystemTime};
+use log::{debug, warn};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 use image::RgbImage;
 
-use crate::images::load_image;
-use crate::utils::Result;
+use crate::backgrounds::Background;
+use crate::images::{Align, Loader, Metadata, RawFormat, ScaleMode};
+use crate::patterns::Pattern;
+use crate::qr::{self, QrStyle};
+use crate::text::{self, TextStyle};
+use crate::transfer::{Transfers, Upload};
+use crate::utils::{self, Result};
 
-const MAX_PACKET_SIZE: usize = 4096;
-const MAX_NAME_LENGTH: usize = MAX_PACKET_SIZE - 64;
+pub const MAX_PACKET_SIZE: usize = 4096;
+pub const MAX_NAME_LENGTH: usize = MAX_PACKET_SIZE - 64;
 const CODE_DISPLAY: u8 = b'd';
 const CODE_ACK:     u8 = b'a';
 const CODE_OK:      u8 = b'o';
 const CODE_ERR:     u8 = b'e';
+const CODE_QUERY:   u8 = b'q';
+const CODE_INFO:    u8 = b'i';
+pub const CODE_CHUNK: u8 = b'c';
+const CODE_MISSING: u8 = b'm';
+/// The params making a display command differ from the last one with the same color and name.
+const DISPLAY_PARAMS: [&str; 19] = ["size", "color", "grid", "qr", "pattern", "background", "region", "align", "offset",
+                                    "zoom", "pan", "text", "font", "font-size", "text-color", "outline", "outline-color",
+                                    "text-anchor", "at"];
+/// The socket receive buffer size of the window process, so bursts of chunks are not dropped.
+const RECV_BUFFER_SIZE: usize = 4 << 20;
+
+/// A background color and an optional image to be displayed by the main loop.
+#[derive(Debug, Clone)]
+pub struct Display {
+    pub color: u32,
+    /// When set, the color is replaced by the average color of the image edges.
+    pub auto_color: bool,
+    pub image: Option<RgbImage>,
+    /// When set, the main loop presents the prepared display at the given time.
+    pub present_at: Option<SystemTime>,
+    /// When set, the window is resized before displaying.
+    pub resize: Option<(usize, usize)>,
+    /// When set, the name is a directory shown as a contact sheet, with file name captions if true.
+    pub grid: Option<bool>,
+    /// When set, the display is shown in the named region instead of the whole window.
+    pub region: Option<String>,
+    /// The path or URL the image was loaded from, empty if not known.
+    pub name: String,
+    /// When set, the image is aligned and moved by the offset instead of the window defaults.
+    pub align: Option<Align>,
+    pub offset: Option<(f64, f64)>,
+    /// When set, the image is zoomed by the factor.
+    pub zoom: Option<f64>,
+    /// When set, the image point given in image pixels is shown in the window center.
+    pub center: Option<(f64, f64)>,
+    /// When set, the image is scaled this way instead of the window or region default.
+    pub scale: Option<ScaleMode>,
+    /// When set, the test pattern is generated at the window or region size instead of showing an image.
+    pub pattern: Option<Pattern>,
+    /// When set, drawn behind the image instead of the flat color.
+    pub background: Option<Background>,
+    /// When set, the caption drawn over the image instead of the window default, none if empty.
+    pub text: Option<String>,
+    pub text_style: TextStyle,
+    /// What is known about the image, for the info overlay.
+    pub meta: Option<Metadata>
+}
+
+impl Display {
+    pub fn new(color: u32, image: Option<RgbImage>) -> Self {
+        Display { color, auto_color: false, image, present_at: None, resize: None, grid: None, region: None, name: String::new(),
+                  align: None, offset: None, zoom: None, center: None, scale: None, pattern: None, background: None,
+                  text: None, text_style: TextStyle::default(),
+                  meta: None }
+    }
+
+    /// Returns the caption text with `{name}` replaced by the image file name, unless it's empty.
+    pub fn caption(&self) -> Option<String> {
+        let text = self.text.as_deref().filter(|text| !text.is_empty())?;
+        let file_name = self.name.rsplit(['/', '\\']).next().unwrap_or_default();
+        Some(text.replace("{name}", file_name))
+    }
+}
+
+/// How the window process identifies itself on the network.
+#[derive(Debug, Clone, Default)]
+pub struct Instance {
+    /// An instance ID attached to each response, may be empty.
+    pub id: String,
+    /// A multicast group to join, IPv6 groups are joined on the interface given by the scope ID.
+    pub group: Option<SocketAddr>,
+    /// The window position reported on discovery.
+    pub xwin: isize,
+    pub ywin: isize,
+    /// The initially displayed background color and image name.
+    pub color: u32,
+    pub name: String,
+    /// The current window size reported on discovery, updated by the main loop.
+    pub size: Arc<Mutex<(usize, usize)>>,
+    /// The JSON information about the image shown in the whole window reported on discovery, updated by the main
+    /// loop, empty if there is none.
+    pub info: Arc<Mutex<String>>,
+    /// The names of the regions displays can be shown in.
+    pub regions: Vec<String>
+}
+
+/// A window process that responded to the discovery query.
+#[derive(Debug, Clone)]
+pub struct Discovered {
+    pub addr: SocketAddr,
+    pub id: String,
+    pub width: u32,
+    pub height: u32,
+    pub xwin: isize,
+    pub ywin: isize,
+    pub port: u16,
+    pub color: u32,
+    pub name: String,
+    /// The JSON information about the shown image, empty if there is none.
+    pub info: String
+}
+
+/// A final response from one of the multicast group members.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub addr: SocketAddr,
+    pub id: String,
+    pub ok: bool
+}
 
 struct Timer {
     start: Instant,
@@ -42,12 +159,90 @@ impl Timer {
     }
 }
 
-pub fn send<A: ToSocketAddrs + Debug, B: ToSocketAddrs>(
-        remote: A,
-        local: B,
+/// Resolves a host name or an IP address to socket addresses.
+///
+/// IPv6 addresses may be enclosed in brackets and may be followed by a `%scope` suffix,
+/// where the scope is either a numeric scope ID or (on unix) a network interface name.
+pub fn resolve(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if let Some((ip, scope)) = host.split_once('%') {
+        let ip: Ipv6Addr = ip.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                                                                 "invalid scoped IPv6 address"))?;
+        let scope_id = match scope.parse() {
+            Ok(scope_id) => scope_id,
+            Err(..) => interface_index(scope)?
+        };
+        return Ok(vec![SocketAddrV6::new(ip, port, 0, scope_id).into()])
+    }
+    let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "couldn't resolve the address"))
+    }
+    Ok(addrs)
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> io::Result<u32> {
+    let cname = std::ffi::CString::new(name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                                                                          "invalid interface name"))?;
+    match unsafe { libc::if_nametoindex(cname.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index)
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> io::Result<u32> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, "the IPv6 scope must be a numeric ID"))
+}
+
+/// Picks one of the `local` addresses to bind to for communicating with the `remote` address.
+///
+/// An unspecified address of the other family is replaced with the unspecified address of the remote's family.
+pub fn local_for(local: &[SocketAddr], remote: SocketAddr) -> SocketAddr {
+    if let Some(addr) = local.iter().find(|addr| addr.is_ipv4() == remote.is_ipv4()) {
+        return *addr
+    }
+    match local.first() {
+        Some(addr) if addr.ip().is_unspecified() && remote.is_ipv4() => (Ipv4Addr::UNSPECIFIED, addr.port()).into(),
+        Some(addr) if addr.ip().is_unspecified() => (Ipv6Addr::UNSPECIFIED, addr.port()).into(),
+        Some(addr) => *addr,
+        None => (Ipv4Addr::UNSPECIFIED, 0).into()
+    }
+}
+
+/// Binds a UDP socket, a socket bound to the unspecified IPv6 address accepts IPv4 traffic as well.
+///
+/// On hosts without IPv6 the unspecified IPv4 address is bound instead.
+pub fn bind_socket(address: SocketAddr) -> io::Result<UdpSocket> {
+    let bind = |address: SocketAddr| -> io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))?;
+        if let SocketAddr::V6(addr) = address {
+            if addr.ip().is_unspecified() {
+                socket.set_only_v6(false)?;
+            }
+        }
+        socket.bind(&address.into())?;
+        Ok(socket.into())
+    };
+    match bind(address) {
+        Err(err) if address.is_ipv6() && address.ip().is_unspecified() => {
+            debug!("binding {} failed, falling back to IPv4: {}", address, err);
+            bind((Ipv4Addr::UNSPECIFIED, address.port()).into())
+        }
+        result => result
+    }
+}
+
+/// Sends a command to the `remote` process and waits for the final response.
+///
+/// The `uploads` are transferred in chunks before the command, the command should reference them in its params.
+pub fn send(
+        remote: SocketAddr,
+        local: SocketAddr,
         timeout: Duration,
-        color: u32,
-        name: &str
+        packet: &RivPacket,
+        uploads: &[Upload]
     ) -> Result<Option<bool>>
 {
     if timeout.as_secs() == 0 {
@@ -55,21 +250,25 @@ pub fn send<A: ToSocketAddrs + Debug, B: ToSocketAddrs>(
     }
 
     const MIN_LOOP_DURATION: Duration = Duration::from_millis(250);
-    let socket = UdpSocket::bind(local)?;
-    if log_enabled!(Level::Debug) {
-        debug!("local {:?}", socket.local_addr()?);
-        for addr in remote.to_socket_addrs()? {
-            debug!("remote {:?}", addr);
-        }
-    }
+    let socket = bind_socket(local)?;
+    debug!("local {:?}", socket.local_addr()?);
+    debug!("remote {:?}", remote);
     socket.connect(remote)?;
     socket.set_read_timeout(Some(MIN_LOOP_DURATION))?;
-    let msg = RivPacket::new(color, name)?.into_inner();
+    let (color, name) = (packet.color(), packet.name());
+    let msg = packet.as_bytes();
     let mut buf = [0; MAX_PACKET_SIZE];
+    for upload in uploads.iter() {
+        upload.send_all(&socket, remote);
+    }
     let mut timer = Timer::new();
+    let mut resend = true;
 
     while timer.timer.duration_since(timer.start) < timeout {
-        let _ = socket.send(&msg);
+        if resend {
+            let _ = socket.send(msg);
+        }
+        resend = true;
         let packet = match socket.recv(&mut buf) {
             Ok(amt) => match RivPacket::from(&buf[0..amt]) {
                 Ok(packet) if !packet.is_display() &&
@@ -87,6 +286,23 @@ pub fn send<A: ToSocketAddrs + Debug, B: ToSocketAddrs>(
         };
         if packet.is_ack() {
             debug!("recv ack");
+            // wait for the final response until the next loop, unless it arrives earlier
+            resend = timer.timer.elapsed() >= MIN_LOOP_DURATION;
+            if resend {
+                timer.wait_if_too_fast(MIN_LOOP_DURATION);
+            }
+            timer.reset();
+        }
+        else if packet.code() == CODE_MISSING {
+            debug!("recv missing");
+            let mut resent = false;
+            for upload in uploads.iter() {
+                resent |= upload.resend_missing(&socket, remote, &packet);
+            }
+            if !resent {
+                debug!("missing transfer is not ours");
+                break
+            }
             timer.wait_if_too_fast(MIN_LOOP_DURATION);
             timer.reset();
         }
@@ -98,33 +314,195 @@ pub fn send<A: ToSocketAddrs + Debug, B: ToSocketAddrs>(
     Ok(None)
 }
 
-pub fn bind<A: ToSocketAddrs>(
-        address: A,
-        buf_width: u32,
-        buf_height: u32,
-        with_info: bool
-    ) -> std::io::Result<Receiver<(u32, Option<RgbImage>)>>
+/// Sends a command to the multicast `group` and collects final responses from every member.
+///
+/// Returns after all members that responded have finished loading the image, or when the
+/// timeout expires without hearing from any pending member.
+pub fn send_group(
+        group: SocketAddr,
+        local: SocketAddr,
+        timeout: Duration,
+        packet: &RivPacket,
+        uploads: &[Upload]
+    ) -> Result<Vec<Response>>
+{
+    let mut responses: Vec<Response> = Vec::new();
+    if timeout.as_secs() == 0 {
+        return Ok(responses)
+    }
+
+    const MIN_LOOP_DURATION: Duration = Duration::from_millis(250);
+    let socket = bind_socket(local)?;
+    debug!("local {:?}", socket.local_addr()?);
+    debug!("group {:?}", group);
+    socket.set_read_timeout(Some(MIN_LOOP_DURATION))?;
+    let (color, name) = (packet.color(), packet.name());
+    let msg = packet.as_bytes();
+    let mut buf = [0; MAX_PACKET_SIZE];
+    let mut pending: Vec<SocketAddr> = Vec::new();
+    for upload in uploads.iter() {
+        upload.send_all(&socket, group);
+    }
+    let mut timer = Timer::new();
+
+    'resend: while timer.timer.duration_since(timer.start) < timeout {
+        let _ = socket.send_to(msg, group);
+        let mut heard = false;
+        while let Ok((amt, src)) = socket.recv_from(&mut buf) {
+            let packet = match RivPacket::from(&buf[0..amt]) {
+                Ok(packet) if !packet.is_display() &&
+                              packet.color() == color &&
+                              packet.name() == name => packet,
+                _ => {
+                    debug!("recv invalid response from {}", src);
+                    continue // not our service or it malfunctions
+                }
+            };
+            if responses.iter().any(|resp| resp.addr == src) {
+                continue
+            }
+            if packet.code() == CODE_MISSING {
+                debug!("recv missing from {}", src);
+                let mut resent = false;
+                for upload in uploads.iter() {
+                    resent |= upload.resend_missing(&socket, group, &packet);
+                }
+                if !resent {
+                    debug!("missing transfer is not ours");
+                    continue
+                }
+            }
+            heard = true;
+            if packet.is_ack() || packet.code() == CODE_MISSING {
+                debug!("recv ack from {}", src);
+                if !pending.contains(&src) {
+                    pending.push(src);
+                }
+                timer.reset();
+            }
+            else {
+                debug!("recv resp {} from {}", packet.is_ok(), src);
+                pending.retain(|addr| *addr != src);
+                responses.push(Response {
+                    addr: src,
+                    id: packet.param("id").unwrap_or("").to_string(),
+                    ok: packet.is_ok()
+                });
+            }
+            if timer.timer.elapsed() >= MIN_LOOP_DURATION {
+                timer.wait_if_too_fast(MIN_LOOP_DURATION);
+                continue 'resend
+            }
+        }
+        if !heard && !responses.is_empty() && pending.is_empty() {
+            break
+        }
+        timer.wait_if_too_fast(MIN_LOOP_DURATION);
+    }
+    Ok(responses)
+}
+
+/// Broadcasts the discovery query to the `target` address and collects responses until the timeout expires.
+pub fn discover(
+        target: SocketAddr,
+        local: SocketAddr,
+        timeout: Duration
+    ) -> Result<Vec<Discovered>>
+{
+    const MIN_LOOP_DURATION: Duration = Duration::from_millis(250);
+    let socket = bind_socket(local)?;
+    debug!("local {:?}", socket.local_addr()?);
+    debug!("target {:?}", target);
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(MIN_LOOP_DURATION))?;
+    let mut msg = RivPacket::new(0, "")?;
+    msg.set_code(CODE_QUERY);
+    let mut buf = [0; MAX_PACKET_SIZE];
+    let mut found: Vec<Discovered> = Vec::new();
+    let mut timer = Timer::new();
+
+    while timer.timer.duration_since(timer.start) < timeout {
+        socket.send_to(msg.as_bytes(), target)?;
+        while let Ok((amt, src)) = socket.recv_from(&mut buf) {
+            let packet = match RivPacket::from(&buf[0..amt]) {
+                Ok(packet) if packet.code() == CODE_INFO => packet,
+                _ => {
+                    debug!("recv invalid response from {}", src);
+                    continue
+                }
+            };
+            if found.iter().any(|inst| inst.addr == src) {
+                continue
+            }
+            debug!("recv info from {}", src);
+            let param = |key| packet.param(key).and_then(|v| v.parse::<i64>().ok()).unwrap_or(0);
+            found.push(Discovered {
+                addr: src,
+                id: packet.param("id").unwrap_or("").to_string(),
+                width: param("width") as u32,
+                height: param("height") as u32,
+                xwin: param("xwin") as isize,
+                ywin: param("ywin") as isize,
+                port: src.port(),
+                color: packet.color(),
+                name: packet.name().to_string(),
+                info: packet.param("info").unwrap_or("").to_string()
+            });
+            if timer.timer.elapsed() >= MIN_LOOP_DURATION {
+                break
+            }
+        }
+        timer.wait_if_too_fast(MIN_LOOP_DURATION);
+    }
+    Ok(found)
+}
+
+pub fn bind(
+        address: SocketAddr,
+        instance: Instance,
+        loader: Loader,
+        main_send: Sender<Display>
+    ) -> std::io::Result<()>
 {
-    let (main_send, main_recv) = channel();
     let (work_send, work_recv) = channel();
     let (netw_send, netw_recv): (Sender<(RivPacket, SocketAddr)>, _) = channel();
 
-    let socket = UdpSocket::bind(address)?;
-    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+    let socket = bind_socket(address)?;
+    if let Err(err) = SockRef::from(&socket).set_recv_buffer_size(RECV_BUFFER_SIZE) {
+        warn!("can't set receive buffer size: {}", err);
+    }
+    // the worker's responses are only checked between requests, so keep it short
+    socket.set_read_timeout(Some(Duration::from_millis(10)))?;
     socket.set_write_timeout(Some(Duration::from_millis(250)))?;
     debug!("bind {:?}", socket.local_addr()?);
+    match instance.group {
+        Some(SocketAddr::V4(group)) => socket.join_multicast_v4(group.ip(), &Ipv4Addr::UNSPECIFIED)?,
+        Some(SocketAddr::V6(group)) => socket.join_multicast_v6(group.ip(), group.scope_id())?,
+        None => {}
+    }
+    if let Some(group) = instance.group {
+        debug!("joined {}", group);
+    }
+    let Instance { id, xwin, ywin, color, name, size, info: shown_info, regions, .. } = instance;
 
     // network service
     thread::spawn(move || {
         let mut udpbuf = [0; MAX_PACKET_SIZE];
-        let mut last_color = 0;
-        let mut last_name = String::new();
+        let mut last_color = color;
+        let mut last_name = name;
         let mut busy = false;
+        let mut transfers = Transfers::default();
+        // the client and the data transfer ID of the last accepted request with the response code
+        let mut last_xfer: Option<(SocketAddr, u32, u8)> = None;
         loop {
             // check worker response
             match netw_recv.try_recv() {
-                Ok((packet, addr)) => {
+                Ok((mut packet, addr)) => {
                     debug!("sending resp to {}", addr);
+                    packet.push_id(&id);
+                    if let Some((.., code)) = last_xfer.as_mut() {
+                        *code = packet.code();
+                    }
                     if packet.is_ok() {
                         last_color = packet.color();
                         last_name.clear();
@@ -136,14 +514,44 @@ pub fn bind<A: ToSocketAddrs>(
                 Err(TryRecvError::Disconnected) => break,
                 _ => {}
             }
+            transfers.purge();
             // check remote request
             let (amt, src) = match socket.recv_from(&mut udpbuf) {
                 Ok(msg) => msg,
                 Err(..) => continue
             };
             // validate packet
-            let packet = match RivPacket::from(&udpbuf[0..amt]) {
+            let mut packet = match RivPacket::from(&udpbuf[0..amt]) {
                 Ok(pkt) if pkt.is_display() => pkt,
+                Ok(pkt) if pkt.code() == CODE_CHUNK => {
+                    if let Err(err) = transfers.insert_chunk(src, &pkt) {
+                        debug!("invalid chunk, ignoring: {}", err);
+                    }
+                    continue;
+                }
+                Ok(pkt) if pkt.code() == CODE_QUERY => {
+                    debug!("query from {}", src);
+                    let (width, height) = *size.lock().unwrap();
+                    let info = RivPacket::new(last_color, &last_name).and_then(|mut info| {
+                        info.set_code(CODE_INFO);
+                        for (key, value) in [("width", width as i64), ("height", height as i64),
+                                             ("xwin", xwin as i64), ("ywin", ywin as i64)] {
+                            info.push_param(key, &value.to_string())?;
+                        }
+                        info.push_id(&id);
+                        // the image information is left out if it doesn't fit in the packet
+                        let shown_info = shown_info.lock().unwrap();
+                        if !shown_info.is_empty() && info.push_param("info", &shown_info).is_err() {
+                            debug!("image info too long: {} bytes", shown_info.len());
+                        }
+                        Ok(info)
+                    });
+                    match info {
+                        Ok(info) => { let _ = socket.send_to(info.as_bytes(), src); }
+                        Err(err) => warn!("can't respond to query: {}", err)
+                    }
+                    continue;
+                }
                 Ok(..) => {
                     debug!("not a display packet, ignoring");
                     continue;                    
@@ -153,42 +561,234 @@ pub fn bind<A: ToSocketAddrs>(
                     continue;
                 }
             };
+            // check referenced transfers
+            let xfers = [packet.param_as::<u32>("data"), packet.param_as::<u32>("longname")];
+            // the data or the long name transfer identifies the command
+            let first_xfer = xfers.iter().flatten().next().copied();
+            if let Some((.., code)) = last_xfer.filter(|&(addr, xfer, _)| addr == src && first_xfer == Some(xfer)) {
+                debug!("repeated transfer: {}", code as char);
+                packet.set_code(code);
+                packet.push_id(&id);
+                let _ = socket.send_to(packet.as_bytes(), src);
+                continue;
+            }
+            if let Some(&xfer) = xfers.iter().flatten().find(|&&xfer| transfers.is_refused(src, xfer)) {
+                warn!("transfer {} from {} refused, too many transfers in progress", xfer, src);
+                packet.set_code(CODE_ERR);
+                packet.push_id(&id);
+                let _ = socket.send_to(packet.as_bytes(), src);
+                continue;
+            }
+            if let Some((xfer, missing)) = xfers.iter().flatten()
+                                                .find_map(|&xfer| transfers.check(src, xfer).err()
+                                                                           .map(|missing| (xfer, missing)))
+            {
+                debug!("incomplete transfer {}: {:?}", xfer, missing);
+                packet.set_code(CODE_MISSING);
+                // the whole transfer is requested again if the list doesn't fit in the packet
+                let pushed = packet.push_param("xfer", &xfer.to_string()).and_then(|_| {
+                    packet.push_param("missing", &missing.encode()).or_else(|_| packet.push_param("missing", "all"))
+                });
+                if let Err(err) = pushed {
+                    warn!("can't request the missing chunks of transfer {}: {}", xfer, err);
+                    packet.set_code(CODE_ERR);
+                }
+                packet.push_id(&id);
+                let _ = socket.send_to(packet.as_bytes(), src);
+                continue;
+            }
             // ACK request
-            udpbuf[RIVOFFS_CODE] = CODE_ACK;
+            let mut code = CODE_ACK;
             // accept request if not busy
             if !busy {
-                if packet.color() == last_color && packet.name() == last_name {
+                if xfers.iter().all(Option::is_none) &&
+                   DISPLAY_PARAMS.iter().all(|param| packet.param(param).is_none()) &&
+                   packet.color() == last_color && packet.name() == last_name
+                {
                     debug!("dupe: #{:06x} {}", packet.color(), packet.name());
                     // respond immediately on dup
-                    udpbuf[RIVOFFS_CODE] = CODE_OK;
+                    code = CODE_OK;
                 }
                 else {
                     debug!("accepted: #{:06x} {}", packet.color(), packet.name());
+                    let [data, longname] = xfers.map(|xfer| xfer.and_then(|xfer| transfers.take(src, xfer).ok()));
+                    last_xfer = first_xfer.map(|xfer| (src, xfer, CODE_ACK));
+                    let job = Job {
+                        packet: packet.clone(),
+                        name: match longname {
+                            Some(name) => String::from_utf8_lossy(&name).into_owned(),
+                            None => packet.name().to_string()
+                        },
+                        data,
+                        addr: src
+                    };
                     // start work
-                    if work_send.send((packet, src)).is_err() {
+                    if work_send.send(job).is_err() {
                         break;
                     }
                     busy = true;
                 }
             }
             // send back ACK or OK
-            let _ = socket.send_to(&udpbuf[0..amt], src);
+            packet.set_code(code);
+            packet.push_id(&id);
+            let _ = socket.send_to(&packet.into_inner(), src);
         }
     });
 
     // image loader
-    let load_for = move |mut packet: RivPacket, addr| -> Result<()> {
-        let name = packet.name();
-        if name.is_empty() {
-            main_send.send((packet.color(), None))?;
+    let load_for = move |Job { mut packet, name, data, addr }| -> Result<()> {
+        let present_at = match packet.param("at").map(|at| at.parse::<u64>()).transpose() {
+            Ok(at) => at.map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis)),
+            Err(..) => {
+                warn!("invalid presentation time: {:?}", packet.param("at"));
+                None
+            }
+        };
+        let resize = match packet.param("size").map(|size| utils::parse_size(size).ok_or(size)).transpose() {
+            Ok(resize) => resize,
+            Err(size) => {
+                warn!("invalid window size, must be 1 - {} on each side: {}", utils::MAX_WINDOW_SIZE, size);
+                packet.set_code(CODE_ERR);
+                netw_send.send((packet, addr))?;
+                return Ok(())
+            }
+        };
+        let region = packet.param("region").map(str::to_string);
+        if let Some(region) = region.as_ref().filter(|region| !regions.contains(region)) {
+            warn!("unknown region: {}", region);
+            packet.set_code(CODE_ERR);
+            netw_send.send((packet, addr))?;
+            return Ok(())
+        }
+        let align = packet.param("align").and_then(|align| align.parse().ok());
+        let offset = packet.param("offset").and_then(utils::parse_point);
+        let zoom = packet.param("zoom").and_then(|zoom| zoom.parse().ok()).filter(|zoom: &f64| *zoom > 0.0);
+        let center = packet.param("pan").and_then(utils::parse_point);
+        let auto_color = packet.param("color") == Some("auto");
+        let params = caption_params(&packet).and_then(|caption| {
+            Ok((caption, packet.param("background").map(Background::parse).transpose()?))
+        });
+        let ((text, text_style), background) = match params {
+            Ok(params) => params,
+            Err(err) => {
+                warn!("{}", err);
+                packet.set_code(CODE_ERR);
+                netw_send.send((packet, addr))?;
+                return Ok(())
+            }
+        };
+        if let Some(grid) = packet.param("grid") {
+            if Path::new(&name).is_dir() {
+                main_send.send(Display {
+                    present_at,
+                    resize,
+                    name,
+                    grid: Some(grid == "captions"),
+                    auto_color,
+                    ..Display::new(packet.color(), None)
+                })?;
+                packet.set_code(CODE_OK);
+            }
+            else {
+                warn!("not a directory: {}", name);
+                packet.set_code(CODE_ERR);
+            }
+        }
+        else if let Some(level) = packet.param("qr") {
+            match qr_style(&packet, level).and_then(|style| qr::render(&name, &style)) {
+                Ok((img, meta)) => {
+                    main_send.send(Display {
+                        present_at,
+                        resize,
+                        region,
+                        align,
+                        offset,
+                        zoom,
+                        center,
+                        scale: Some(ScaleMode::Integer),
+                        auto_color,
+                        text,
+                        text_style,
+                        background: background.clone(),
+                        meta: Some(meta),
+                        ..Display::new(packet.color(), Some(img))
+                    })?;
+                    packet.set_code(CODE_OK);
+                }
+                Err(err) => {
+                    warn!("rendering QR code failed: {}", err);
+                    packet.set_code(CODE_ERR);
+                }
+            }
+        }
+        else if let Some(pattern) = packet.param("pattern") {
+            match pattern.parse::<Pattern>() {
+                Ok(pattern) => {
+                    main_send.send(Display {
+                        present_at,
+                        resize,
+                        region,
+                        pattern: Some(pattern),
+                        text,
+                        text_style,
+                        background: background.clone(),
+                        ..Display::new(packet.color(), None)
+                    })?;
+                    packet.set_code(CODE_OK);
+                }
+                Err(err) => {
+                    warn!("{}", err);
+                    packet.set_code(CODE_ERR);
+                }
+            }
+        }
+        else if name.is_empty() && data.is_none() {
+            main_send.send(Display {
+                present_at,
+                resize,
+                region,
+                text,
+                text_style,
+                background,
+                ..Display::new(packet.color(), None)
+            })?;
             packet.set_code(CODE_OK);
         }
         else {
-            debug!("loading: {}", name);
-            match load_image(&name, buf_width, buf_height, with_info) {
-                Ok(img) => {
+            let raw = packet.param("raw").map(|raw| raw.parse::<RawFormat>());
+            let (img, name) = match (data, raw) {
+                (Some(data), Some(Ok(format))) => (loader.load_raw(&data, &format), String::new()),
+                (_, Some(Err(err))) => (Err(err.into()), String::new()),
+                (None, Some(..)) => (Err("raw frame requires data".into()), String::new()),
+                (Some(data), None) => {
+                    debug!("loading {} bytes: {}", data.len(), name);
+                    (loader.load_from_memory(&data), String::new())
+                }
+                (None, None) => {
+                    debug!("loading: {}", name);
+                    (loader.load(&name), name)
+                }
+            };
+            match img {
+                Ok((img, meta)) => {
                     // send to main to show it
-                    main_send.send((packet.color(), Some(img)))?;
+                    main_send.send(Display {
+                        present_at,
+                        resize,
+                        region,
+                        name,
+                        align,
+                        offset,
+                        zoom,
+                        center,
+                        auto_color,
+                        text,
+                        text_style,
+                        background: background.clone(),
+                        meta: Some(meta),
+                        ..Display::new(packet.color(), Some(img))
+                    })?;
                     packet.set_code(CODE_OK);
                 }
                 Err(err) => {
@@ -204,14 +804,48 @@ pub fn bind<A: ToSocketAddrs>(
 
     // image load worker
     thread::spawn(move || {
-        for (packet, addr) in work_recv.iter() {
-            if load_for(packet, addr).is_err() {
+        for job in work_recv.iter() {
+            if load_for(job).is_err() {
                 break
             }
         }
     });
 
-    Ok(main_recv)
+    Ok(())
+}
+
+/// Reads the caption text and its style, loading the font file on the window process side.
+fn caption_params(packet: &RivPacket) -> Result<(Option<String>, TextStyle)> {
+    let text_style = TextStyle {
+        font: packet.param("font").map(text::load_font).transpose()?,
+        size: packet.param_as("font-size").filter(|size: &f32| *size > 0.0 && size.is_finite()),
+        color: packet.param("text-color").and_then(utils::parse_color),
+        outline: packet.param_as("outline").filter(|outline: &f32| *outline >= 0.0),
+        outline_color: packet.param("outline-color").and_then(utils::parse_color),
+        anchor: packet.param_as("text-anchor")
+    };
+    Ok((packet.param("text").map(str::to_string), text_style))
+}
+
+/// Reads the QR code style, the params not given are set to the defaults.
+fn qr_style(packet: &RivPacket, level: &str) -> Result<QrStyle> {
+    let default = QrStyle::default();
+    Ok(QrStyle {
+        level: qr::parse_level(level)?,
+        margin: packet.param_as("qr-margin").filter(|margin| *margin <= qr::MAX_MARGIN).unwrap_or(default.margin),
+        color: packet.param("qr-color").and_then(utils::parse_color).unwrap_or(default.color),
+        background: packet.param("qr-background").and_then(utils::parse_color).unwrap_or(default.background)
+    })
+}
+
+/// A display request accepted by the network service for the image loader.
+struct Job {
+    packet: RivPacket,
+    /// The image name, possibly transferred in chunks.
+    name: String,
+    /// The image file content transferred in chunks.
+    data: Option<Vec<u8>>,
+    addr: SocketAddr
 }
 
 /*
@@ -219,15 +853,50 @@ RIVd - picture to display
 RIVa - ack
 RIVo - picture shown
 RIVe - error
+RIVq - discovery query
+RIVi - discovery response with the current color, image name and window geometry params
+RIVc - a chunk of data, see transfer.rs
+RIVm - a transfer is incomplete, see transfer.rs
+
+"RIV", "d"|"a"|"o"|"e"|"q"|"i"|"c"|"m", color BE u32, filename size BE u16, filename,
+      [params size BE u16, params, [payload]]
 
-"RIV", "d"|"a"|"o"|"e", color BE u32, filename size BE u16, filename
+params: "key=value" entries separated with "\n":
+
+"id=..." - responding instance ID
+"width=...", "height=...", "xwin=...", "ywin=..." - window geometry
+"info=..." - the JSON information about the image shown in the whole window, see hud.rs
+"size=WIDTHxHEIGHT" - resize the window before displaying
+"color=auto" - use the average color of the image edges instead of the color
+"grid=plain|captions" - show the directory given as the filename as a contact sheet, see grid.rs
+"qr=L|M|Q|H" - show the filename encoded as a QR code with the error correction level, see qr.rs
+"qr-margin=..." - the width of the quiet zone around the QR code in modules
+"qr-color=...", "qr-background=..." - the colors of the dark and the light QR code modules
+"pattern=..." - show a test pattern generated at the window or region size, see patterns::Pattern
+"background=..." - a gradient, a tiled image or the blurred image drawn behind the image, see backgrounds.rs
+"region=..." - show the image in the named region of the window, see regions.rs
+"align=..." - align the image, see images::Align
+"offset=X,Y" - move the aligned image by the given window pixels
+"zoom=..." - zoom the image by the factor
+"pan=X,Y" - show the image point given in image pixels in the window center
+"text=..." - the caption drawn over the image, "{name}" is replaced with the file name and "\n" breaks lines
+"font=..." - the path of a TTF or OTF font file for the caption, read by the window process
+"font-size=...", "outline=..." - the caption line height and outline width in pixels
+"text-color=...", "outline-color=..." - the caption colors, see text::TextStyle
+"text-anchor=..." - where the caption is placed in the window, see images::Align
+"at=..." - present the image at the given time (UNIX epoch milliseconds) after it's been loaded
+"data=..." - the ID of a transfer with the image file content, the filename is only informative
+"raw=..." - the data is an uncompressed frame, see images::RawFormat
+"longname=..." - the ID of a transfer with the filename exceeding the packet size, the filename field is ignored
 */
 const RIVOFFS_CODE: usize = 3;
 const RIVOFFS_COLOR: usize = 4;
 const RIVOFFS_COLOR_END: usize = RIVOFFS_COLOR + mem::size_of::<u32>();
 const RIVOFFS_NAMELEN: usize = RIVOFFS_COLOR_END;
 const RIVOFFS_NAME: usize = RIVOFFS_NAMELEN + mem::size_of::<u16>();
+const PARAMS_LEN_SIZE: usize = mem::size_of::<u16>();
 
+#[derive(Clone)]
 pub struct RivPacket {
     data: Vec<u8>
 }
@@ -249,6 +918,10 @@ impl RivPacket {
         self.data
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn from(data: &[u8]) -> result::Result<Self, &str> {
         if data.len() < RIVOFFS_NAME {
             return Err("packet too short");
@@ -258,16 +931,129 @@ impl RivPacket {
             return Err("wrong packet file name size");
         }
         match &data[0..4] {
-            b"RIVd"|b"RIVa"|b"RIVo"|b"RIVe" => {}
+            b"RIVd"|b"RIVa"|b"RIVo"|b"RIVe"|b"RIVq"|b"RIVi"|b"RIVc"|b"RIVm" => {}
             _ => return Err("invalid packet magick")
         }
-        core::str::from_utf8(&data[RIVOFFS_NAME..]).map_err(|_| "can't decode UTF-8")?;
+        let name_end = RIVOFFS_NAME + name_size as usize;
+        core::str::from_utf8(&data[RIVOFFS_NAME..name_end]).map_err(|_| "can't decode UTF-8")?;
+        match data.len() - name_end {
+            0 => {}
+            1 => return Err("wrong packet params size"),
+            _ => {
+                let params_size = u16::from_be_bytes(data[name_end..name_end + PARAMS_LEN_SIZE].try_into().unwrap());
+                let params = &data[name_end + PARAMS_LEN_SIZE..];
+                if params.len() < params_size as usize {
+                    return Err("wrong packet params size");
+                }
+                core::str::from_utf8(&params[0..params_size as usize]).map_err(|_| "can't decode UTF-8")?;
+            }
+        }
         Ok(RivPacket { data: data.to_vec() })
     }
 
+    fn name_end(&self) -> usize {
+        RIVOFFS_NAME + u16::from_be_bytes(self.data[RIVOFFS_NAMELEN..RIVOFFS_NAME].try_into().unwrap()) as usize
+    }
+
+    /// Returns the range of the params section or `None` if the packet has none.
+    fn params_range(&self) -> Option<core::ops::Range<usize>> {
+        let name_end = self.name_end();
+        self.data.get(name_end..name_end + PARAMS_LEN_SIZE).map(|size| {
+            let start = name_end + PARAMS_LEN_SIZE;
+            start..start + u16::from_be_bytes(size.try_into().unwrap()) as usize
+        })
+    }
+
     pub fn name(&self) -> &str {
         // we did check at init
-        unsafe { core::str::from_utf8_unchecked(&self.data[RIVOFFS_NAME..]) }
+        unsafe { core::str::from_utf8_unchecked(&self.data[RIVOFFS_NAME..self.name_end()]) }
+    }
+
+    fn params_str(&self) -> &str {
+        match self.params_range() {
+            // we did check at init
+            Some(range) => unsafe { core::str::from_utf8_unchecked(&self.data[range]) },
+            None => ""
+        }
+    }
+
+    /// Returns an iterator of parameter key and value pairs.
+    pub fn params(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.params_str().split('\n')
+                         .filter(|entry| !entry.is_empty())
+                         .map(|entry| match entry.find('=') {
+                            Some(index) => (&entry[0..index], &entry[index + 1..]),
+                            None => (entry, "")
+                         })
+    }
+
+    /// Returns the value of the first parameter with the given `key`.
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params().find(|&(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Returns the value of the first parameter with the given `key` parsed as `T`.
+    pub fn param_as<T: core::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.param(key).and_then(|v| v.parse().ok())
+    }
+
+    /// Ensures the packet has the params section and returns its range.
+    fn params_range_mut(&mut self) -> core::ops::Range<usize> {
+        match self.params_range() {
+            Some(range) => range,
+            None => {
+                self.data.extend_from_slice(&[0; PARAMS_LEN_SIZE]);
+                let end = self.data.len();
+                end..end
+            }
+        }
+    }
+
+    /// Appends a parameter to the packet.
+    pub fn push_param(&mut self, key: &str, value: &str) -> result::Result<(), &'static str> {
+        if key.is_empty() || key.contains(['=', '\n']) || value.contains('\n') {
+            return Err("invalid packet parameter");
+        }
+        let range = self.params_range_mut();
+        let separator = if range.is_empty() { 0 } else { 1 };
+        let entry_size = separator + key.len() + 1 + value.len();
+        if self.data.len() + entry_size > MAX_PACKET_SIZE {
+            return Err("parameters are too long to encode in a packet");
+        }
+        let entry = (separator == 1).then_some(&b"\n"[..]).into_iter()
+                    .chain([key.as_bytes(), b"=", value.as_bytes()])
+                    .flatten().copied();
+        self.data.splice(range.end..range.end, entry);
+        let params_size = (range.len() + entry_size) as u16;
+        self.data[range.start - PARAMS_LEN_SIZE..range.start].copy_from_slice(&params_size.to_be_bytes());
+        Ok(())
+    }
+
+    fn push_id(&mut self, id: &str) {
+        if !id.is_empty() {
+            if let Err(err) = self.push_param("id", id) {
+                warn!("can't attach instance ID: {}", err);
+            }
+        }
+    }
+
+    /// Returns the binary data following the params section.
+    pub fn payload(&self) -> &[u8] {
+        match self.params_range() {
+            Some(range) => &self.data[range.end..],
+            None => &[]
+        }
+    }
+
+    /// Replaces the binary data following the params section.
+    pub fn set_payload(&mut self, payload: &[u8]) -> result::Result<(), &'static str> {
+        let range = self.params_range_mut();
+        if range.end + payload.len() > MAX_PACKET_SIZE {
+            return Err("payload is too long to encode in a packet");
+        }
+        self.data.truncate(range.end);
+        self.data.extend_from_slice(payload);
+        Ok(())
     }
 
     pub fn color(&self) -> u32 {
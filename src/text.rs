@@ -0,0 +1,246 @@
+use std::fs;
+use std::sync::Arc;
+use font8x8::{UnicodeFonts, BASIC_FONTS, LATIN_FONTS};
+use fontdue::{Font, FontSettings};
+
+use crate::images::Align;
+use crate::utils::Result;
+
+/// The width and height of a glyph of the built-in bitmap font.
+pub const GLYPH_SIZE: usize = 8;
+/// The caption defaults.
+pub const DEFAULT_TEXT_SIZE: f32 = 32.0;
+pub const DEFAULT_TEXT_COLOR: u32 = 0xffffff;
+pub const DEFAULT_OUTLINE_COLOR: u32 = 0;
+/// Outlines are limited to keep drawing them fast.
+pub const MAX_OUTLINE: f32 = 16.0;
+/// The line height is limited, so the text masks can be allocated.
+pub const MAX_FONT_SIZE: f32 = 1024.0;
+
+/// How a caption is drawn, unset values are taken from the window defaults, then from the constants above.
+#[derive(Debug, Clone, Default)]
+pub struct TextStyle {
+    /// An outline font, the built-in bitmap font enlarged to the nearest multiple of its size is used if not set.
+    pub font: Option<Arc<Font>>,
+    /// The line height in pixels.
+    pub size: Option<f32>,
+    pub color: Option<u32>,
+    /// The width of the outline drawn around the glyphs, in pixels.
+    pub outline: Option<f32>,
+    pub outline_color: Option<u32>,
+    /// Where the caption is placed in the window, the bottom center by default.
+    pub anchor: Option<Align>
+}
+
+impl TextStyle {
+    /// Returns the style with the values not set taken from the `base`.
+    pub fn or(self, base: &TextStyle) -> TextStyle {
+        TextStyle {
+            font: self.font.or_else(|| base.font.clone()),
+            size: self.size.or(base.size),
+            color: self.color.or(base.color),
+            outline: self.outline.or(base.outline),
+            outline_color: self.outline_color.or(base.outline_color),
+            anchor: self.anchor.or(base.anchor)
+        }
+    }
+}
+
+/// Loads a TTF or OTF font file.
+pub fn load_font(path: &str) -> Result<Arc<Font>> {
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    let font = Font::from_bytes(data, FontSettings::default()).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(Arc::new(font))
+}
+
+/// The opacity (0 - 255) of each pixel of a rendered text.
+struct Mask {
+    width: usize,
+    height: usize,
+    alpha: Vec<u8>
+}
+
+impl Mask {
+    fn new(width: usize, height: usize) -> Self {
+        Mask { width, height, alpha: vec![0; width * height] }
+    }
+
+    /// Merges the `src` pixels into the mask at the position, keeping the more opaque ones.
+    fn merge(&mut self, src: &[u8], src_width: usize, x: isize, y: isize) {
+        for (row, line) in src.chunks(src_width.max(1)).enumerate() {
+            let my = y + row as isize;
+            if my < 0 || my as usize >= self.height {
+                continue
+            }
+            for (col, &alpha) in line.iter().enumerate() {
+                let mx = x + col as isize;
+                if mx >= 0 && (mx as usize) < self.width {
+                    let dst = &mut self.alpha[my as usize * self.width + mx as usize];
+                    *dst = (*dst).max(alpha);
+                }
+            }
+        }
+    }
+
+    /// Returns the mask grown by the radius on each side, for drawing outlines.
+    fn dilate(&self, radius: usize) -> Mask {
+        let mut grown = Mask::new(self.width + 2 * radius, self.height + 2 * radius);
+        let r = radius as isize;
+        for dy in -r..=r {
+            for dx in (-r..=r).filter(|dx| dx * dx + dy * dy <= r * r) {
+                grown.merge(&self.alpha, self.width, r + dx, r + dy);
+            }
+        }
+        grown
+    }
+}
+
+/// A buffer of pixels in the 0RGB format.
+pub struct Canvas<'a> {
+    pub buf: &'a mut [u32],
+    pub width: usize,
+    pub height: usize
+}
+
+impl Canvas<'_> {
+    /// Blends the pixel with the color with the given opacity (0 - 255).
+    #[inline]
+    pub fn blend(&mut self, x: isize, y: isize, color: u32, alpha: u8) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return
+        }
+        let pixel = &mut self.buf[y as usize * self.width + x as usize];
+        *pixel = blend(*pixel, color, alpha);
+    }
+
+    /// Fills the rectangle with the color blended with the given opacity.
+    pub fn fill_rect(&mut self, x: isize, y: isize, width: usize, height: usize, color: u32, alpha: u8) {
+        for row in y..y + height as isize {
+            for col in x..x + width as isize {
+                self.blend(col, row, color, alpha);
+            }
+        }
+    }
+
+    /// Draws a caption of one or more lines, separated with new lines or `\n`, in the style.
+    pub fn draw_caption(&mut self, text: &str, style: &TextStyle) {
+        let size = style.size.unwrap_or(DEFAULT_TEXT_SIZE).clamp(1.0, MAX_FONT_SIZE);
+        let anchor = style.anchor.unwrap_or(Align::Bottom);
+        let mask = render_text(&text.replace("\\n", "\n"), style.font.as_deref(), size, anchor.fractions().0);
+        let outline = style.outline.unwrap_or(0.0).clamp(0.0, MAX_OUTLINE).round() as usize;
+        let margin = (size / 2.0).round() as isize;
+        let (fx, fy) = anchor.fractions();
+        let place = |space: usize, extent: usize, fraction: f64| {
+            margin + ((space as isize - 2 * margin - extent as isize) as f64 * fraction).round() as isize
+        };
+        let x = place(self.width, mask.width + 2 * outline, fx);
+        let y = place(self.height, mask.height + 2 * outline, fy);
+        if outline != 0 {
+            self.draw_mask(&mask.dilate(outline), x, y, style.outline_color.unwrap_or(DEFAULT_OUTLINE_COLOR));
+        }
+        self.draw_mask(&mask, x + outline as isize, y + outline as isize, style.color.unwrap_or(DEFAULT_TEXT_COLOR));
+    }
+
+    fn draw_mask(&mut self, mask: &Mask, x: isize, y: isize, color: u32) {
+        for (row, line) in mask.alpha.chunks(mask.width.max(1)).enumerate() {
+            for (col, &alpha) in line.iter().enumerate().filter(|(_, &alpha)| alpha != 0) {
+                self.blend(x + col as isize, y + row as isize, color, alpha);
+            }
+        }
+    }
+
+    /// Draws a line of text with the built-in 8x8 font enlarged `scale` times.
+    pub fn draw_text(&mut self, x: isize, y: isize, text: &str, color: u32, scale: usize) {
+        for (index, ch) in text.chars().enumerate() {
+            let glyph = glyph(ch);
+            let left = x + (index * GLYPH_SIZE * scale) as isize;
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in (0..GLYPH_SIZE).filter(|col| bits & (1 << col) != 0) {
+                    let (px, py) = (left + (col * scale) as isize, y + (row * scale) as isize);
+                    self.fill_rect(px, py, scale, scale, color, 255);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the size of the text drawn with the built-in font.
+pub fn text_size(text: &str, scale: usize) -> (usize, usize) {
+    (text.chars().count() * GLYPH_SIZE * scale, GLYPH_SIZE * scale)
+}
+
+/// Renders the lines of text, each placed at the horizontal `fraction` of the free space.
+fn render_text(text: &str, font: Option<&Font>, size: f32, fraction: f64) -> Mask {
+    let lines: Vec<Mask> = text.lines().map(|line| match font {
+        Some(font) => render_line(line, font, size),
+        None => render_bitmap_line(line, ((size / GLYPH_SIZE as f32).round() as usize).max(1))
+    }).collect();
+    let spacing = (size / 4.0).round() as usize;
+    let width = lines.iter().map(|line| line.width).max().unwrap_or(0);
+    let height = lines.iter().map(|line| line.height + spacing).sum::<usize>().saturating_sub(spacing);
+    let mut mask = Mask::new(width, height);
+    let mut y = 0;
+    for line in lines.iter() {
+        let x = ((width - line.width) as f64 * fraction).round() as isize;
+        mask.merge(&line.alpha, line.width, x, y as isize);
+        y += line.height + spacing;
+    }
+    mask
+}
+
+fn render_line(line: &str, font: &Font, size: f32) -> Mask {
+    let (ascent, descent) = font.horizontal_line_metrics(size).map_or((size, 0.0), |lm| (lm.ascent, lm.descent));
+    let mut glyphs = Vec::new();
+    let mut pen = 0.0f32;
+    let mut width = 0;
+    let mut prev = None;
+    for ch in line.chars() {
+        if let Some(kern) = prev.and_then(|prev| font.horizontal_kern(prev, ch, size)) {
+            pen += kern;
+        }
+        let (metrics, bitmap) = font.rasterize(ch, size);
+        let x = (pen + metrics.xmin as f32).round() as isize;
+        let y = (ascent - (metrics.ymin + metrics.height as i32) as f32).round() as isize;
+        width = width.max((x + metrics.width as isize).max(0) as usize);
+        glyphs.push((x, y, metrics.width, bitmap));
+        pen += metrics.advance_width;
+        prev = Some(ch);
+    }
+    let mut mask = Mask::new(width.max(pen.ceil() as usize), (ascent - descent).ceil() as usize);
+    for (x, y, glyph_width, bitmap) in glyphs {
+        mask.merge(&bitmap, glyph_width, x, y);
+    }
+    mask
+}
+
+fn render_bitmap_line(line: &str, scale: usize) -> Mask {
+    let (width, height) = text_size(line, scale);
+    let mut mask = Mask::new(width, height);
+    let block = vec![255; scale * scale];
+    for (index, ch) in line.chars().enumerate() {
+        for (row, bits) in glyph(ch).iter().enumerate() {
+            for col in (0..GLYPH_SIZE).filter(|col| bits & (1 << col) != 0) {
+                let x = (index * GLYPH_SIZE + col) * scale;
+                mask.merge(&block, scale, x as isize, (row * scale) as isize);
+            }
+        }
+    }
+    mask
+}
+
+fn glyph(ch: char) -> [u8; 8] {
+    BASIC_FONTS.get(ch).or_else(|| LATIN_FONTS.get(ch))
+               .or_else(|| BASIC_FONTS.get('?'))
+               .unwrap_or_default()
+}
+
+#[inline]
+pub fn blend(pixel: u32, color: u32, alpha: u8) -> u32 {
+    if alpha == 255 {
+        return color
+    }
+    let [_, r0, g0, b0] = pixel.to_be_bytes();
+    let [_, r1, g1, b1] = color.to_be_bytes();
+    let mix = |c0: u8, c1: u8| ((c0 as u32 * (255 - alpha as u32) + c1 as u32 * alpha as u32) / 255) as u8;
+    u32::from_be_bytes([0, mix(r0, r1), mix(g0, g1), mix(b0, b1)])
+}
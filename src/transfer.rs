@@ -0,0 +1,430 @@
+use core::result;
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use log::debug;
+
+use crate::remote::{RivPacket, MAX_PACKET_SIZE, CODE_CHUNK};
+
+/// The size of data carried by a single chunk packet.
+pub const CHUNK_SIZE: usize = MAX_PACKET_SIZE - 64;
+/// The maximum size of data accepted in a single transfer.
+pub const MAX_TRANSFER_SIZE: usize = 256 << 20;
+/// Incomplete transfers are forgotten after this duration of inactivity.
+const TRANSFER_EXPIRE: Duration = Duration::from_secs(30);
+/// The maximum number of incomplete transfers, in total and from a single IP address.
+const MAX_TRANSFERS: usize = 64;
+const MAX_SOURCE_TRANSFERS: usize = 4;
+/// The maximum size of the data of all incomplete transfers, two of the largest ones including their last chunks.
+const MAX_RESERVED_SIZE: usize = 2 * MAX_TRANSFER_SIZE.div_ceil(CHUNK_SIZE) * CHUNK_SIZE;
+/// The maximum length of the missing chunks list in a single response.
+const MAX_MISSING_LENGTH: usize = 1024;
+/// How many chunks are sent at once before pausing for a while.
+const CHUNK_BURST: usize = 32;
+
+/*
+RIVc - a chunk of data
+
+"RIVc", 0, 0, params, chunk data
+
+params:
+
+"xfer=..." - transfer ID
+"seq=..."  - chunk sequence number, counted from 0
+"total=..." - total number of chunks in the transfer
+
+RIVm - a response to a display packet referencing an incomplete transfer: the display packet with params:
+
+"xfer=..." - transfer ID
+"missing=..." - "all" or a list of comma separated missing chunk numbers or ranges, e.g. "1,4-7"
+
+Up to 64 transfers of 512 MiB in total, 4 from a single IP address, are kept until complete, chunks of further
+transfers are dropped and display packets referencing them get RIVe.
+*/
+
+/// Data to be uploaded to the remote process in chunks before sending a display command.
+#[derive(Debug, Clone, Copy)]
+pub struct Upload<'a> {
+    pub id: u32,
+    pub data: &'a [u8]
+}
+
+impl<'a> Upload<'a> {
+    pub fn new(data: &'a [u8]) -> result::Result<Self, &'static str> {
+        if data.is_empty() {
+            return Err("no data to transfer");
+        }
+        if data.len() > MAX_TRANSFER_SIZE {
+            return Err("data is too large to transfer");
+        }
+        Ok(Upload { id: new_id(), data })
+    }
+
+    pub fn total(&self) -> u32 {
+        chunk_count(self.data.len())
+    }
+
+    fn chunk(&self, seq: u32) -> Option<RivPacket> {
+        let start = seq as usize * CHUNK_SIZE;
+        let chunk = self.data.get(start..(start + CHUNK_SIZE).min(self.data.len()))?;
+        let mut packet = RivPacket::new(0, "").ok()?;
+        packet.set_code(CODE_CHUNK);
+        packet.push_param("xfer", &self.id.to_string()).ok()?;
+        packet.push_param("seq", &seq.to_string()).ok()?;
+        packet.push_param("total", &self.total().to_string()).ok()?;
+        packet.set_payload(chunk).ok()?;
+        Some(packet)
+    }
+
+    /// Sends chunks with the given sequence numbers to the `target`.
+    pub fn send_chunks<I: IntoIterator<Item=u32>>(
+            &self,
+            socket: &UdpSocket,
+            target: SocketAddr,
+            seqs: I
+        )
+    {
+        for (index, seq) in seqs.into_iter().enumerate() {
+            if let Some(packet) = self.chunk(seq) {
+                let _ = socket.send_to(packet.as_bytes(), target);
+            }
+            // give the receiver a chance to keep up
+            if index % CHUNK_BURST == CHUNK_BURST - 1 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    /// Sends all the chunks to the `target`.
+    pub fn send_all(&self, socket: &UdpSocket, target: SocketAddr) {
+        debug!("uploading {} bytes in {} chunks", self.data.len(), self.total());
+        self.send_chunks(socket, target, 0..self.total())
+    }
+
+    /// Re-sends chunks listed in the missing response packet, if it concerns this upload.
+    pub fn resend_missing(&self, socket: &UdpSocket, target: SocketAddr, packet: &RivPacket) -> bool {
+        if packet.param_as::<u32>("xfer") != Some(self.id) {
+            return false
+        }
+        let missing = packet.param("missing").unwrap_or("");
+        debug!("resending missing chunks: {}", missing);
+        if missing == "all" {
+            self.send_all(socket, target);
+        }
+        else {
+            self.send_chunks(socket, target, parse_missing(missing, self.total()));
+        }
+        true
+    }
+}
+
+pub fn chunk_count(size: usize) -> u32 {
+    size.div_ceil(CHUNK_SIZE) as u32
+}
+
+fn new_id() -> u32 {
+    let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+                                 .unwrap_or_default().subsec_nanos();
+    nanos ^ std::process::id().rotate_left(16)
+}
+
+/// Chunks not yet received by the transfer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Missing {
+    /// The transfer is unknown.
+    All,
+    Chunks(Vec<u32>)
+}
+
+impl Missing {
+    /// Encodes the missing list as a packet parameter value, truncated to fit in the packet.
+    pub fn encode(&self) -> String {
+        let seqs = match self {
+            Missing::All => return "all".to_string(),
+            Missing::Chunks(seqs) => seqs
+        };
+        let mut out = String::new();
+        let mut iter = seqs.iter().copied().peekable();
+        while let Some(start) = iter.next() {
+            let mut end = start;
+            while iter.peek() == Some(&(end + 1)) {
+                end = iter.next().unwrap();
+            }
+            let entry = if start == end { start.to_string() } else { format!("{}-{}", start, end) };
+            if out.len() + entry.len() + 1 > MAX_MISSING_LENGTH {
+                break
+            }
+            if !out.is_empty() {
+                out.push(',');
+            }
+            out.push_str(&entry);
+        }
+        out
+    }
+}
+
+fn parse_missing(missing: &str, total: u32) -> Vec<u32> {
+    let mut seqs = Vec::new();
+    for entry in missing.split(',') {
+        let (start, end) = match entry.split_once('-') {
+            Some((start, end)) => (start.parse::<u32>(), end.parse::<u32>()),
+            None => (entry.parse::<u32>(), entry.parse::<u32>())
+        };
+        if let (Ok(start), Ok(end)) = (start, end) {
+            seqs.extend(start..end.saturating_add(1).min(total));
+        }
+    }
+    seqs
+}
+
+struct Transfer {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    updated: Instant
+}
+
+impl Transfer {
+    fn missing(&self) -> Vec<u32> {
+        self.chunks.iter().enumerate()
+                   .filter(|(_, chunk)| chunk.is_none())
+                   .map(|(seq, _)| seq as u32)
+                   .collect()
+    }
+}
+
+/// Reassembles chunked transfers received from remote clients.
+#[derive(Default)]
+pub struct Transfers {
+    map: HashMap<(SocketAddr, u32), Transfer>,
+    /// The size of the data of the transfers in the map when complete.
+    reserved: usize,
+    /// The transfers not accepted because of the limits, with the time of their last chunk.
+    refused: HashMap<(SocketAddr, u32), Instant>,
+    purged: Option<Instant>
+}
+
+impl Transfers {
+    /// Stores the data of the chunk packet received from `src`.
+    pub fn insert_chunk(&mut self, src: SocketAddr, packet: &RivPacket) -> result::Result<(), &'static str> {
+        let (id, seq, total): (u32, u32, u32) = match (packet.param_as("xfer"),
+                                                       packet.param_as("seq"),
+                                                       packet.param_as("total")) {
+            (Some(id), Some(seq), Some(total)) => (id, seq, total),
+            _ => return Err("missing chunk parameters")
+        };
+        if seq >= total || total as usize > chunk_count(MAX_TRANSFER_SIZE) as usize {
+            return Err("invalid chunk sequence number");
+        }
+        let data = packet.payload();
+        if data.len() > CHUNK_SIZE || (data.len() < CHUNK_SIZE && seq + 1 != total) {
+            return Err("invalid chunk size");
+        }
+        let size = total as usize * CHUNK_SIZE;
+        let reserved = self.reserved - self.map.get(&(src, id)).map_or(0, |transfer| transfer.chunks.len() * CHUNK_SIZE);
+        let new = !self.map.contains_key(&(src, id));
+        let source_transfers = self.map.keys().filter(|(addr, _)| addr.ip() == src.ip()).count();
+        if reserved + size > MAX_RESERVED_SIZE ||
+           new && (self.map.len() >= MAX_TRANSFERS || source_transfers >= MAX_SOURCE_TRANSFERS)
+        {
+            if self.refused.len() < MAX_TRANSFERS || self.refused.contains_key(&(src, id)) {
+                self.refused.insert((src, id), Instant::now());
+            }
+            return Err("too many transfers in progress");
+        }
+        self.refused.remove(&(src, id));
+        let transfer = self.map.entry((src, id)).or_insert_with(|| Transfer {
+            chunks: Vec::new(),
+            received: 0,
+            updated: Instant::now()
+        });
+        if transfer.chunks.len() != total as usize {
+            debug!("new transfer {} from {}: {} chunks", id, src, total);
+            transfer.chunks = vec![None; total as usize];
+            transfer.received = 0;
+            self.reserved = reserved + size;
+        }
+        let chunk = &mut transfer.chunks[seq as usize];
+        if chunk.is_none() {
+            *chunk = Some(data.to_vec());
+            transfer.received += 1;
+        }
+        transfer.updated = Instant::now();
+        Ok(())
+    }
+
+    /// Returns whether the transfer from `src` was refused because of the limits.
+    pub fn is_refused(&self, src: SocketAddr, id: u32) -> bool {
+        self.refused.contains_key(&(src, id))
+    }
+
+    /// Checks if the transfer from `src` is complete.
+    pub fn check(&self, src: SocketAddr, id: u32) -> result::Result<(), Missing> {
+        match self.map.get(&(src, id)) {
+            Some(transfer) if transfer.received == transfer.chunks.len() => Ok(()),
+            Some(transfer) => Err(Missing::Chunks(transfer.missing())),
+            None => Err(Missing::All)
+        }
+    }
+
+    /// Removes the complete transfer from `src` and returns its data.
+    pub fn take(&mut self, src: SocketAddr, id: u32) -> result::Result<Vec<u8>, Missing> {
+        self.check(src, id)?;
+        let transfer = self.map.remove(&(src, id)).unwrap();
+        self.reserved -= transfer.chunks.len() * CHUNK_SIZE;
+        Ok(transfer.chunks.into_iter().flatten().flatten().collect())
+    }
+
+    /// Forgets transfers inactive for too long.
+    pub fn purge(&mut self) {
+        if self.purged.is_some_and(|purged| purged.elapsed() < TRANSFER_EXPIRE) {
+            return
+        }
+        self.purged = Some(Instant::now());
+        let reserved = &mut self.reserved;
+        self.map.retain(|(src, id), transfer| {
+            let keep = transfer.updated.elapsed() < TRANSFER_EXPIRE;
+            if !keep {
+                debug!("transfer {} from {} expired", id, src);
+                *reserved -= transfer.chunks.len() * CHUNK_SIZE;
+            }
+            keep
+        });
+        self.refused.retain(|_, updated| updated.elapsed() < TRANSFER_EXPIRE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn chunk(id: u32, seq: u32, total: u32, data: &[u8]) -> RivPacket {
+        let mut packet = RivPacket::new(0, "").unwrap();
+        packet.set_code(CODE_CHUNK);
+        packet.push_param("xfer", &id.to_string()).unwrap();
+        packet.push_param("seq", &seq.to_string()).unwrap();
+        packet.push_param("total", &total.to_string()).unwrap();
+        packet.set_payload(data).unwrap();
+        packet
+    }
+
+    #[test]
+    fn encodes_missing_ranges() {
+        assert_eq!(Missing::All.encode(), "all");
+        assert_eq!(Missing::Chunks(vec![]).encode(), "");
+        assert_eq!(Missing::Chunks(vec![0, 1, 2, 5, 7, 8]).encode(), "0-2,5,7-8");
+        let seqs: Vec<u32> = (0..10_000).step_by(2).collect();
+        let encoded = Missing::Chunks(seqs.clone()).encode();
+        assert!(encoded.len() <= MAX_MISSING_LENGTH && !encoded.ends_with(','));
+        let decoded = parse_missing(&encoded, 10_000);
+        assert!(!decoded.is_empty() && seqs.starts_with(&decoded));
+    }
+
+    #[test]
+    fn parses_missing_ranges() {
+        assert_eq!(parse_missing("1,4-7", 10), [1, 4, 5, 6, 7]);
+        assert_eq!(parse_missing("x,3,-,2-,-1,5-a,", 10), [3]);
+        // ranges are clamped to the chunks of the upload
+        assert_eq!(parse_missing("5-100", 8), [5, 6, 7]);
+        assert_eq!(parse_missing("9", 5), []);
+        assert_eq!(parse_missing("0", 0), []);
+        assert_eq!(parse_missing("7-3", 10), []);
+        assert_eq!(parse_missing("", 10), []);
+    }
+
+    #[test]
+    fn rejects_invalid_chunks() {
+        let mut transfers = Transfers::default();
+        let src = addr("10.0.0.1:1000");
+        let full = [0; CHUNK_SIZE];
+        let mut packet = RivPacket::new(0, "").unwrap();
+        packet.set_code(CODE_CHUNK);
+        packet.push_param("xfer", "1").unwrap();
+        assert!(transfers.insert_chunk(src, &packet).is_err());
+        assert!(transfers.insert_chunk(src, &chunk(1, 2, 2, &full)).is_err());
+        assert!(transfers.insert_chunk(src, &chunk(1, 0, chunk_count(MAX_TRANSFER_SIZE) + 1, &full)).is_err());
+        // only the last chunk can be shorter
+        assert!(transfers.insert_chunk(src, &chunk(1, 0, 2, &full[1..])).is_err());
+        assert!(transfers.insert_chunk(src, &chunk(1, 1, 2, &full[1..])).is_ok());
+        assert!(transfers.insert_chunk(src, &chunk(1, 0, 2, &full)).is_ok());
+        assert_eq!(transfers.check(src, 1), Ok(()));
+        assert!(!transfers.is_refused(src, 1));
+    }
+
+    #[test]
+    fn limits_transfers_per_source() {
+        let mut transfers = Transfers::default();
+        for port in 0..MAX_SOURCE_TRANSFERS as u16 {
+            let src = SocketAddr::new(addr("10.0.0.1:0").ip(), port);
+            assert!(transfers.insert_chunk(src, &chunk(1, 0, 2, &[0; CHUNK_SIZE])).is_ok());
+        }
+        let src = addr("10.0.0.1:9999");
+        assert!(transfers.insert_chunk(src, &chunk(1, 0, 2, &[0; CHUNK_SIZE])).is_err());
+        assert!(transfers.is_refused(src, 1));
+        assert_eq!(transfers.check(src, 1), Err(Missing::All));
+        // the chunks of the transfers in progress are still accepted
+        assert!(transfers.insert_chunk(addr("10.0.0.1:0"), &chunk(1, 1, 2, &[0; 1])).is_ok());
+        assert!(transfers.insert_chunk(addr("10.0.0.2:9999"), &chunk(1, 0, 2, &[0; CHUNK_SIZE])).is_ok());
+    }
+
+    #[test]
+    fn limits_reserved_size() {
+        let mut transfers = Transfers::default();
+        let total = chunk_count(MAX_TRANSFER_SIZE);
+        assert!(transfers.insert_chunk(addr("10.0.0.1:1"), &chunk(1, 0, total, &[0; CHUNK_SIZE])).is_ok());
+        assert!(transfers.insert_chunk(addr("10.0.0.2:1"), &chunk(1, 0, total, &[0; CHUNK_SIZE])).is_ok());
+        assert_eq!(transfers.reserved, MAX_RESERVED_SIZE);
+        let src = addr("10.0.0.3:1");
+        assert!(transfers.insert_chunk(src, &chunk(1, 0, 1, &[0; 1])).is_err());
+        assert!(transfers.is_refused(src, 1));
+        // a refused transfer is accepted once there is room for it
+        transfers.map.get_mut(&(addr("10.0.0.1:1"), 1)).unwrap().updated = Instant::now().checked_sub(TRANSFER_EXPIRE).unwrap();
+        transfers.purge();
+        assert_eq!(transfers.reserved, total as usize * CHUNK_SIZE);
+        assert!(transfers.insert_chunk(src, &chunk(1, 0, 1, &[0; 1])).is_ok());
+        assert!(!transfers.is_refused(src, 1));
+    }
+
+    #[test]
+    fn reassembles_transfers() {
+        let mut transfers = Transfers::default();
+        let src = addr("[::1]:1000");
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 10).map(|index| index as u8).collect();
+        let upload = Upload { id: 7, data: &data };
+        assert_eq!(upload.total(), 3);
+        for seq in [2, 0] {
+            transfers.insert_chunk(src, &upload.chunk(seq).unwrap()).unwrap();
+        }
+        assert_eq!(transfers.check(src, 7), Err(Missing::Chunks(vec![1])));
+        assert_eq!(transfers.take(src, 7), Err(Missing::Chunks(vec![1])));
+        // repeated chunks are ignored
+        for seq in [1, 1, 2] {
+            transfers.insert_chunk(src, &upload.chunk(seq).unwrap()).unwrap();
+        }
+        assert_eq!(transfers.reserved, 3 * CHUNK_SIZE);
+        assert_eq!(transfers.check(addr("[::1]:1001"), 7), Err(Missing::All));
+        assert_eq!(transfers.take(src, 7), Ok(data));
+        assert_eq!(transfers.reserved, 0);
+        assert_eq!(transfers.check(src, 7), Err(Missing::All));
+    }
+
+    #[test]
+    fn purges_expired_transfers() {
+        let mut transfers = Transfers::default();
+        let expired = Instant::now().checked_sub(TRANSFER_EXPIRE * 2).unwrap();
+        for (port, id) in [(1, 1), (2, 2)] {
+            transfers.insert_chunk(addr(&format!("10.0.0.1:{}", port)), &chunk(id, 0, 2, &[0; CHUNK_SIZE])).unwrap();
+        }
+        transfers.map.get_mut(&(addr("10.0.0.1:1"), 1)).unwrap().updated = expired;
+        transfers.refused.insert((addr("10.0.0.9:1"), 3), expired);
+        transfers.purge();
+        assert_eq!(transfers.check(addr("10.0.0.1:1"), 1), Err(Missing::All));
+        assert_eq!(transfers.check(addr("10.0.0.1:2"), 2), Err(Missing::Chunks(vec![1])));
+        assert_eq!(transfers.reserved, 2 * CHUNK_SIZE);
+        assert!(!transfers.is_refused(addr("10.0.0.9:1"), 3));
+    }
+}
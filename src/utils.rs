@@ -1,8 +1,16 @@
 #![allow(unused_imports)]
 use std::{borrow::Cow, error::Error, fmt, ptr};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+use css_color_parser::Color as CssColor;
+
+use crate::images;
 
 pub type Result<T> = core::result::Result<T, Box<dyn std::error::Error>>;
 
+/// The largest window width or height, so the buffers of requested sizes can be allocated.
+pub const MAX_WINDOW_SIZE: usize = 16384;
+
 #[derive(Debug, Clone)]
 pub struct ExitError {
     message: &'static str,
@@ -33,6 +41,70 @@ pub fn err_code(msg: &'static str, code: i32) -> Result<()> {
     Err(ExitError::new(msg, code).into())
 }
 
+/// Returns the number of milliseconds since the UNIX epoch, or 0 for times before it.
+pub fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Parses `+millis` as a time relative to now or `millis` as milliseconds since the UNIX epoch.
+pub fn parse_time(value: &str) -> core::result::Result<SystemTime, std::num::ParseIntError> {
+    if let Some(delay) = value.strip_prefix('+') {
+        Ok(SystemTime::now() + Duration::from_millis(delay.parse()?))
+    }
+    else {
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_millis(value.parse()?))
+    }
+}
+
+/// Parses a window size: "WIDTHxHEIGHT", each side from 1 to `MAX_WINDOW_SIZE`.
+pub fn parse_size(value: &str) -> Option<(usize, usize)> {
+    let (width, height) = value.split_once('x')?;
+    let valid = |side: &usize| (1..=MAX_WINDOW_SIZE).contains(side);
+    Some((width.parse().ok().filter(valid)?, height.parse().ok().filter(valid)?))
+}
+
+/// Parses a point: "X,Y".
+pub fn parse_point(value: &str) -> Option<(f64, f64)> {
+    let (x, y) = value.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?)).filter(|&(x, y): &(f64, f64)| x.is_finite() && y.is_finite())
+}
+
+/// Parses a CSS color.
+pub fn parse_color(value: &str) -> Option<u32> {
+    value.parse::<CssColor>().ok().map(|CssColor { r, g, b, .. }| images::from_u8_rgb(r, g, b))
+}
+
+/// Converts IPv4-mapped IPv6 addresses, as seen by dual-stack sockets, to plain IPv4 addresses.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// Formats an IP address with its IPv6 scope ID, if any, as accepted by the address options.
+pub fn format_ip(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V6(addr) if addr.scope_id() != 0 => format!("{}%{}", addr.ip(), addr.scope_id()),
+        addr => addr.ip().to_string()
+    }
+}
+
+/// Returns the string as a quoted JSON string literal.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
 
 #[cfg(not(windows))]
 pub fn set_dpi_awareness() -> core::result::Result<(), String> { Ok(()) }
@@ -56,6 +128,48 @@ pub fn set_dpi_awareness() -> core::result::Result<(), String> {
     }
 }
 
+/// Returns the size of the primary screen in pixels.
+#[cfg(windows)]
+pub fn screen_size() -> Option<(usize, usize)> {
+    use winapi::um::winuser::{GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN};
+
+    let (width, height) = unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) };
+    (width > 0 && height > 0).then_some((width as usize, height as usize))
+}
+
+/// Returns the size of the primary screen in pixels.
+#[cfg(target_os = "macos")]
+pub fn screen_size() -> Option<(usize, usize)> {
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGMainDisplayID() -> u32;
+        fn CGDisplayPixelsWide(display: u32) -> usize;
+        fn CGDisplayPixelsHigh(display: u32) -> usize;
+    }
+
+    let (width, height) = unsafe {
+        let display = CGMainDisplayID();
+        (CGDisplayPixelsWide(display), CGDisplayPixelsHigh(display))
+    };
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Returns the size of the default X11 screen in pixels.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn screen_size() -> Option<(usize, usize)> {
+    let xlib = x11_dl::xlib::Xlib::open().ok()?;
+    unsafe {
+        let display = (xlib.XOpenDisplay)(ptr::null());
+        if display.is_null() {
+            return None
+        }
+        let screen = (xlib.XDefaultScreen)(display);
+        let size = ((xlib.XDisplayWidth)(display, screen), (xlib.XDisplayHeight)(display, screen));
+        (xlib.XCloseDisplay)(display);
+        (size.0 > 0 && size.1 > 0).then_some((size.0 as usize, size.1 as usize))
+    }
+}
+
 #[cfg(not(windows))]
 pub fn free_console_window() {}
 
@@ -0,0 +1,418 @@
+use core::convert::TryInto;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+use log::{debug, warn};
+
+use crate::mirror::{Frame, Mirror};
+
+/// How often the client messages are checked while waiting for the window contents to change.
+const POLL_DURATION: Duration = Duration::from_millis(100);
+const ENCODING_RAW: i32 = 0;
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+const MAX_CLIENTS: usize = 16;
+/// Clients not completing the handshake in time are disconnected.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/*
+A minimal RFB 3.3 - 3.8 server (RFC 6143) without authentication, sending the Raw encoding only.
+
+Key events are forwarded to the main loop only if input is enabled, pointer events are ignored.
+*/
+
+/// A key pressed or released in a VNC client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub keysym: u32,
+    pub down: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PixelFormat {
+    bits_per_pixel: u8,
+    big_endian: bool,
+    max: [u16; 3],
+    shift: [u8; 3]
+}
+
+impl PixelFormat {
+    /// The native format of the window buffer.
+    const NATIVE: PixelFormat = PixelFormat {
+        bits_per_pixel: 32,
+        big_endian: false,
+        max: [255, 255, 255],
+        shift: [16, 8, 0]
+    };
+
+    fn parse(data: &[u8; 16]) -> io::Result<Self> {
+        let format = PixelFormat {
+            bits_per_pixel: data[0],
+            big_endian: data[2] != 0,
+            max: [u16::from_be_bytes([data[4], data[5]]),
+                  u16::from_be_bytes([data[6], data[7]]),
+                  u16::from_be_bytes([data[8], data[9]])],
+            shift: [data[10], data[11], data[12]]
+        };
+        if data[3] == 0 || !matches!(format.bits_per_pixel, 8|16|32) {
+            return Err(invalid("unsupported pixel format"))
+        }
+        // the shifted color values must fit in a pixel
+        let bits = format.bits_per_pixel as u32;
+        if format.max.iter().zip(format.shift.iter())
+                 .any(|(&max, &shift)| shift as u32 >= bits || (max as u64) << shift >= 1 << bits)
+        {
+            return Err(invalid("pixel format color values don't fit in a pixel"))
+        }
+        Ok(format)
+    }
+
+    fn encode(&self) -> [u8; 16] {
+        let [rmax, gmax, bmax] = self.max.map(u16::to_be_bytes);
+        [self.bits_per_pixel, 24, self.big_endian as u8, 1,
+         rmax[0], rmax[1], gmax[0], gmax[1], bmax[0], bmax[1],
+         self.shift[0], self.shift[1], self.shift[2], 0, 0, 0]
+    }
+
+    fn write_pixel(&self, pixel: u32, out: &mut Vec<u8>) {
+        let value = if *self == Self::NATIVE {
+            pixel
+        }
+        else {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b].iter().zip(self.max.iter().zip(self.shift.iter()))
+                     .fold(0, |value, (&c, (&max, &shift))| {
+                         value | (c as u32 * max as u32 / 255) << shift
+                     })
+        };
+        match (self.bits_per_pixel, self.big_endian) {
+            (8, _) => out.push(value as u8),
+            (16, false) => out.extend_from_slice(&(value as u16).to_le_bytes()),
+            (16, true) => out.extend_from_slice(&(value as u16).to_be_bytes()),
+            (_, false) => out.extend_from_slice(&value.to_le_bytes()),
+            (_, true) => out.extend_from_slice(&value.to_be_bytes())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize
+}
+
+enum ClientMessage {
+    SetPixelFormat(PixelFormat),
+    SetEncodings(Vec<i32>),
+    UpdateRequest { incremental: bool, rect: Rect }
+}
+
+pub fn bind(
+        address: SocketAddr,
+        name: String,
+        mirror: Mirror,
+        input: Option<Sender<KeyEvent>>
+    ) -> io::Result<()>
+{
+    let listener = TcpListener::bind(address)?;
+    debug!("vnc bind {:?}", listener.local_addr()?);
+    let clients = Arc::new(AtomicUsize::new(0));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!("vnc accept failed: {}", err);
+                    continue;
+                }
+            };
+            if clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                clients.fetch_sub(1, Ordering::SeqCst);
+                warn!("too many vnc clients, rejecting {:?}", stream.peer_addr());
+                continue;
+            }
+            let (name, mirror, input) = (name.clone(), mirror.clone(), input.clone());
+            let slot = ClientSlot(clients.clone());
+            thread::spawn(move || {
+                let _slot = slot;
+                let peer = stream.peer_addr();
+                debug!("vnc client {:?}", peer);
+                if let Err(err) = serve(stream, &name, &mirror, input) {
+                    debug!("vnc client {:?}: {}", peer, err);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// Frees the place of a client when its thread ends, even by panicking.
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn handshake(stream: &mut TcpStream, name: &str, width: usize, height: usize) -> io::Result<()> {
+    stream.write_all(b"RFB 003.008\n")?;
+    let version: [u8; 12] = read_array(stream)?;
+    let minor = match &version {
+        [b'R', b'F', b'B', b' ', b'0', b'0', b'3', b'.', minor @ .., b'\n'] => {
+            core::str::from_utf8(minor).ok().and_then(|minor| minor.parse::<u32>().ok())
+                                       .ok_or_else(|| invalid("invalid protocol version"))?
+        }
+        _ => return Err(invalid("not an RFB client"))
+    };
+    debug!("vnc protocol 3.{}", minor);
+    if minor < 7 {
+        // the server decides: no authentication
+        stream.write_all(&1u32.to_be_bytes())?;
+    }
+    else {
+        stream.write_all(&[1, 1])?;
+        let [security] = read_array(stream)?;
+        if security != 1 {
+            return Err(invalid("unsupported security type"))
+        }
+        if minor >= 8 {
+            stream.write_all(&0u32.to_be_bytes())?;
+        }
+    }
+    // ClientInit, the shared flag is ignored, all clients share the display
+    let [_shared] = read_array(stream)?;
+    let mut init = Vec::with_capacity(24 + name.len());
+    init.extend_from_slice(&(width as u16).to_be_bytes());
+    init.extend_from_slice(&(height as u16).to_be_bytes());
+    init.extend_from_slice(&PixelFormat::NATIVE.encode());
+    init.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    init.extend_from_slice(name.as_bytes());
+    stream.write_all(&init)
+}
+
+fn read_messages(mut stream: TcpStream, send: Sender<ClientMessage>, input: Option<Sender<KeyEvent>>) -> io::Result<()> {
+    loop {
+        let [kind] = read_array(&mut stream)?;
+        let msg = match kind {
+            0 => {
+                let _pad: [u8; 3] = read_array(&mut stream)?;
+                ClientMessage::SetPixelFormat(PixelFormat::parse(&read_array(&mut stream)?)?)
+            }
+            2 => {
+                let [_pad, hi, lo] = read_array(&mut stream)?;
+                let count = u16::from_be_bytes([hi, lo]) as usize;
+                let mut encodings = Vec::with_capacity(count);
+                for _ in 0..count {
+                    encodings.push(i32::from_be_bytes(read_array(&mut stream)?));
+                }
+                ClientMessage::SetEncodings(encodings)
+            }
+            3 => {
+                let data: [u8; 9] = read_array(&mut stream)?;
+                let field = |index: usize| u16::from_be_bytes([data[index], data[index + 1]]) as usize;
+                ClientMessage::UpdateRequest {
+                    incremental: data[0] != 0,
+                    rect: Rect { x: field(1), y: field(3), width: field(5), height: field(7) }
+                }
+            }
+            4 => {
+                let data: [u8; 7] = read_array(&mut stream)?;
+                let key = KeyEvent { keysym: u32::from_be_bytes(data[3..7].try_into().unwrap()), down: data[0] != 0 };
+                debug!("vnc key: {:?}", key);
+                if let Some(input) = input.as_ref() {
+                    let _ = input.send(key);
+                }
+                continue
+            }
+            5 => {
+                let _pointer: [u8; 5] = read_array(&mut stream)?;
+                continue
+            }
+            6 => {
+                let data: [u8; 7] = read_array(&mut stream)?;
+                let len = u32::from_be_bytes(data[3..7].try_into().unwrap());
+                io::copy(&mut (&mut stream).take(len as u64), &mut io::sink())?;
+                continue
+            }
+            _ => return Err(invalid("unsupported client message"))
+        };
+        if send.send(msg).is_err() {
+            return Ok(())
+        }
+    }
+}
+
+fn serve(mut stream: TcpStream, name: &str, mirror: &Mirror, input: Option<Sender<KeyEvent>>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+    let (width, height) = mirror.with_frame(|frame| (frame.width, frame.height));
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    handshake(&mut stream, name, width, height)?;
+    stream.set_read_timeout(None)?;
+
+    let (send, recv) = channel();
+    let reader = stream.try_clone()?;
+    thread::spawn(move || {
+        if let Err(err) = read_messages(reader, send, input) {
+            debug!("vnc read: {}", err);
+        }
+    });
+    let mut writer = BufWriter::new(stream);
+    let mut client = Client {
+        format: PixelFormat::NATIVE,
+        desktop_size: false,
+        sent: Frame { generation: u64::MAX, width, height, buffer: Vec::new() }
+    };
+    let mut request: Option<(bool, Rect)> = None;
+    loop {
+        let msg = if request.is_none() {
+            recv.recv().map_err(|_| TryRecvError::Disconnected)
+        }
+        else {
+            recv.try_recv()
+        };
+        match msg {
+            Ok(ClientMessage::SetPixelFormat(format)) => {
+                debug!("vnc pixel format: {:?}", format);
+                client.format = format;
+            }
+            Ok(ClientMessage::SetEncodings(encodings)) => {
+                debug!("vnc encodings: {:?}", encodings);
+                client.desktop_size = encodings.contains(&ENCODING_DESKTOP_SIZE);
+            }
+            Ok(ClientMessage::UpdateRequest { incremental, rect }) => {
+                // a full update request supersedes a pending incremental one
+                if request.is_none_or(|(incremental, _)| incremental) {
+                    request = Some((incremental, rect));
+                }
+            }
+            Err(TryRecvError::Disconnected) => return Ok(()),
+            Err(TryRecvError::Empty) => {
+                if let Some((incremental, rect)) = request {
+                    let changed = !incremental ||
+                                  mirror.wait_newer(client.sent.generation, POLL_DURATION) != client.sent.generation;
+                    if changed && client.update(&mut writer, mirror, incremental, rect)? {
+                        request = None;
+                    }
+                }
+                continue
+            }
+        }
+    }
+}
+
+struct Client {
+    format: PixelFormat,
+    desktop_size: bool,
+    /// The copy of the last frame sent.
+    sent: Frame
+}
+
+impl Client {
+    /// Sends the changes of the requested area, returns `false` if there was nothing to send.
+    fn update(&mut self, writer: &mut impl Write, mirror: &Mirror, incremental: bool, rect: Rect) -> io::Result<bool> {
+        let resized = mirror.with_frame(|frame| {
+            let resized = (frame.width, frame.height) != (self.sent.width, self.sent.height);
+            if incremental && !resized && self.sent.generation == frame.generation {
+                return None
+            }
+            // update the copy only
+            let changed = if incremental && !resized && self.sent.buffer.len() == frame.buffer.len() {
+                changed_rect(&self.sent.buffer, &frame.buffer, frame.width)
+            }
+            else {
+                Some(Rect { x: 0, y: 0, width: frame.width, height: frame.height })
+            };
+            self.sent.generation = frame.generation;
+            self.sent.width = frame.width;
+            self.sent.height = frame.height;
+            self.sent.buffer.clear();
+            self.sent.buffer.extend_from_slice(&frame.buffer);
+            Some((resized, changed))
+        });
+        let (resized, changed) = match resized {
+            Some(update) => update,
+            None => return Ok(false)
+        };
+        let Frame { width, height, .. } = self.sent;
+        let mut out = Vec::new();
+        let mut rects = Vec::with_capacity(2);
+        if resized && self.desktop_size {
+            debug!("vnc desktop size: {}x{}", width, height);
+            rects.push((Rect { x: 0, y: 0, width, height }, ENCODING_DESKTOP_SIZE));
+        }
+        let area = if resized { Rect { x: 0, y: 0, width, height } } else { rect };
+        if let Some(changed) = changed.and_then(|changed| intersect(changed, area)) {
+            rects.push((changed, ENCODING_RAW));
+        }
+        else if incremental {
+            return Ok(false)
+        }
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&(rects.len() as u16).to_be_bytes());
+        for (rect, encoding) in rects {
+            for value in [rect.x, rect.y, rect.width, rect.height] {
+                out.extend_from_slice(&(value as u16).to_be_bytes());
+            }
+            out.extend_from_slice(&encoding.to_be_bytes());
+            if encoding == ENCODING_RAW {
+                for row in rect.y..rect.y + rect.height {
+                    let start = row * width + rect.x;
+                    for &pixel in self.sent.buffer[start..start + rect.width].iter() {
+                        self.format.write_pixel(pixel, &mut out);
+                    }
+                }
+            }
+        }
+        if let Err(err) = writer.write_all(&out).and_then(|_| writer.flush()) {
+            warn!("vnc update failed: {}", err);
+            return Err(err)
+        }
+        Ok(true)
+    }
+}
+
+/// The bounding rectangle of the pixels which differ.
+fn changed_rect(old: &[u32], new: &[u32], width: usize) -> Option<Rect> {
+    let rows: Vec<usize> = old.chunks_exact(width).zip(new.chunks_exact(width)).enumerate()
+                              .filter(|(_, (old, new))| old != new)
+                              .map(|(row, _)| row)
+                              .collect();
+    let (&top, &bottom) = (rows.first()?, rows.last()?);
+    let (mut left, mut right) = (width, 0);
+    for &row in rows.iter() {
+        let range = row * width..(row + 1) * width;
+        let (old, new) = (&old[range.clone()], &new[range]);
+        if let Some(first) = old.iter().zip(new.iter()).position(|(a, b)| a != b) {
+            left = left.min(first);
+            let last = width - 1 - old.iter().rev().zip(new.iter().rev()).position(|(a, b)| a != b).unwrap();
+            right = right.max(last);
+        }
+    }
+    Some(Rect { x: left, y: top, width: right + 1 - left, height: bottom + 1 - top })
+}
+
+fn intersect(a: Rect, b: Rect) -> Option<Rect> {
+    let (x, y) = (a.x.max(b.x), a.y.max(b.y));
+    let (right, bottom) = ((a.x + a.width).min(b.x + b.width), (a.y + a.height).min(b.y + b.height));
+    (right > x && bottom > y).then(|| Rect { x, y, width: right - x, height: bottom - y })
+}
+
@@ -0,0 +1,169 @@
+use core::fmt;
+use core::str::FromStr;
+use std::time::Instant;
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
+
+use crate::text::{Canvas, TextStyle};
+
+/// The time zone of the clock and of the countdown target time.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Zone {
+    /// The time zone of the system.
+    #[default]
+    Local,
+    /// A fixed offset from UTC, e.g. "+02:00".
+    Fixed(FixedOffset),
+    /// A time zone from the IANA database, e.g. "Europe/Warsaw" or "UTC".
+    Named(Tz)
+}
+
+impl Zone {
+    fn format(self, time: DateTime<Utc>, format: &str) -> String {
+        match self {
+            Zone::Local => time.with_timezone(&Local).format(format).to_string(),
+            Zone::Fixed(offset) => time.with_timezone(&offset).format(format).to_string(),
+            Zone::Named(tz) => time.with_timezone(&tz).format(format).to_string()
+        }
+    }
+
+    fn date(self, time: DateTime<Utc>) -> NaiveDate {
+        match self {
+            Zone::Local => time.with_timezone(&Local).date_naive(),
+            Zone::Fixed(offset) => time.with_timezone(&offset).date_naive(),
+            Zone::Named(tz) => time.with_timezone(&tz).date_naive()
+        }
+    }
+
+    /// Converts the date and time in the zone to UTC, the earlier one if it's ambiguous.
+    fn to_utc(self, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+        fn convert<Z: TimeZone>(zone: &Z, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+            zone.from_local_datetime(&time).earliest().map(|time| time.with_timezone(&Utc))
+        }
+        match self {
+            Zone::Local => convert(&Local, time),
+            Zone::Fixed(offset) => convert(&offset, time),
+            Zone::Named(tz) => convert(&tz, time)
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local)
+        }
+        s.parse().map(Zone::Named)
+         .or_else(|_| s.parse().map(Zone::Fixed))
+         .map_err(|_| "time zone must be local, an IANA time zone name or an offset: +HH:MM")
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::Local => f.write_str("local"),
+            Zone::Fixed(offset) => offset.fmt(f),
+            Zone::Named(tz) => f.write_str(tz.name())
+        }
+    }
+}
+
+/// Returns whether the clock format is a valid strftime format.
+pub fn is_valid_format(format: &str) -> bool {
+    StrftimeItems::new(format).all(|item| item != Item::Error)
+}
+
+/// Parses the countdown target time: "+SECONDS" from now, an RFC 3339 time, "YYYY-MM-DD HH:MM[:SS]"
+/// in the time zone or "HH:MM[:SS]", the next such time in the time zone.
+pub fn parse_target(value: &str, zone: Zone) -> Result<DateTime<Utc>, &'static str> {
+    let now = Utc::now();
+    if let Some(seconds) = value.strip_prefix('+') {
+        return seconds.parse().ok().and_then(Duration::try_seconds).map(|duration| now + duration)
+                      .ok_or("countdown seconds must be an integer")
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc))
+    }
+    let value = value.replacen('T', " ", 1);
+    if let Some(time) = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"].iter()
+                                                                .find_map(|format| NaiveDateTime::parse_from_str(&value, format).ok())
+    {
+        return zone.to_utc(time).ok_or("countdown time doesn't exist in the time zone")
+    }
+    let time = ["%H:%M:%S", "%H:%M"].iter().find_map(|format| NaiveTime::parse_from_str(&value, format).ok())
+                                  .ok_or("countdown must be +SECONDS, YYYY-MM-DD HH:MM[:SS] or HH:MM[:SS]")?;
+    let today = zone.date(now);
+    [today, today.succ_opt().unwrap_or(today)].iter()
+        .filter_map(|date| zone.to_utc(date.and_time(time)))
+        .find(|target| *target > now)
+        .ok_or("countdown time doesn't exist in the time zone")
+}
+
+/// The clock, the countdown and the elapsed timer, one per line, drawn over the window contents.
+pub struct Widgets {
+    /// The strftime format of the clock.
+    clock: Option<String>,
+    zone: Zone,
+    countdown: Option<DateTime<Utc>>,
+    /// When the elapsed timer started.
+    started: Option<Instant>,
+    style: TextStyle,
+    /// The text currently drawn.
+    text: String
+}
+
+impl Widgets {
+    /// Returns `None` if no widget is enabled.
+    pub fn new(
+            clock: Option<&str>,
+            zone: Zone,
+            countdown: Option<DateTime<Utc>>,
+            elapsed: bool,
+            style: TextStyle
+        ) -> Option<Self>
+    {
+        (clock.is_some() || countdown.is_some() || elapsed).then(|| Widgets {
+            clock: clock.map(str::to_string),
+            zone,
+            countdown,
+            started: elapsed.then(Instant::now),
+            style,
+            text: String::new()
+        })
+    }
+
+    /// Updates the text for the current time, returns whether it has changed.
+    pub fn update(&mut self) -> bool {
+        let now = Utc::now();
+        let mut lines = Vec::new();
+        if let Some(format) = self.clock.as_deref() {
+            lines.push(self.zone.format(now, format));
+        }
+        if let Some(target) = self.countdown {
+            // rounded up, so zero is shown when the target time is reached
+            let millis = (target - now).num_milliseconds().max(0);
+            lines.push(format_seconds((millis + 999) / 1000));
+        }
+        if let Some(started) = self.started {
+            lines.push(format_seconds(started.elapsed().as_secs() as i64));
+        }
+        let text = lines.join("\n");
+        if text == self.text {
+            return false
+        }
+        self.text = text;
+        true
+    }
+
+    pub fn draw(&self, buffer: &mut [u32], width: usize, height: usize) {
+        Canvas { buf: buffer, width, height }.draw_caption(&self.text, &self.style);
+    }
+}
+
+fn format_seconds(seconds: i64) -> String {
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}